@@ -15,4 +15,6 @@ mkdir -p /.cache && chown -R hoard /.cache
 chown -R hoard /data
 
 # Start the application as the user
-su hoard -c /hoard
\ No newline at end of file
+# exec so hoard receives the stop signal, su would kill it after two seconds.
+# setpriv keeps the environment of root, yt-dlp needs the home of hoard for its cache
+exec setpriv --reuid=hoard --regid=hoard --init-groups env HOME="$(getent passwd hoard | cut -d: -f6)" USER=hoard /hoard
\ No newline at end of file
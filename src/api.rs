@@ -0,0 +1,210 @@
+//! HTTP API to add URLs to the download queue, enabled with `[hoard] api = "127.0.0.1:8080"`.
+//!
+//! `POST /queue` with a JSON body like `{"url": "...", "module": "youtube", "dir": "misc"}`
+//! queues the URL like `hoard add`, `GET /status` lists the modules and how often they were restarted.
+//! The body needs `Content-Type: application/json`, with `api_token` set requests also need
+//! `Authorization: Bearer <token>`.
+
+use std::{
+    io::Read,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, PoisonError, RwLock,
+    },
+    thread::{JoinHandle, Scope},
+    time::Duration,
+};
+
+use serde::Deserialize;
+use serde_json::json;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::{config::GlobalConfig, db::Database, queue, shutdown, supervisor};
+
+/// How often the server checks whether a shutdown was requested
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Largest request body read in bytes
+const MAX_BODY: u64 = 64 * 1024;
+/// Most URLs resolved at once, further `POST /queue` requests are turned away
+const MAX_RESOLVING: usize = 4;
+
+/// The config requests are checked against, updated by reloads
+static CONFIG: RwLock<Option<Arc<GlobalConfig>>> = RwLock::new(None);
+
+/// Body of `POST /queue`
+#[derive(Debug, Deserialize)]
+struct AddRequest {
+    url: String,
+    module: String,
+    /// Directory relative to the module directory, [`queue::DEFAULT_DIR`] if unset
+    dir: Option<String>,
+}
+
+/// What to do with a request after checking it
+enum Action {
+    Reply(u16, serde_json::Value),
+    Queue(Arc<GlobalConfig>, AddRequest),
+}
+
+/// Set the config the API uses, called again after every reload
+pub fn set_config(config: &GlobalConfig) {
+    *CONFIG.write().unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(config.clone()));
+}
+
+/// Start the API server on `address`, it runs until a shutdown is requested.
+///
+/// URLs are resolved in threads of their own, so a slow `yt-dlp` does not hold up other requests.
+pub fn spawn(
+    address: &str,
+    config: &GlobalConfig,
+    db: Database,
+    dry_run: bool,
+) -> Result<JoinHandle<()>, String> {
+    let server =
+        Server::http(address).map_err(|e| format!("Could not start the API on {address}: {e}"))?;
+    log::info!("API listening on {address}");
+    set_config(config);
+
+    Ok(std::thread::spawn(move || {
+        let resolving = AtomicUsize::new(0);
+        // waits for the URLs still resolving before the thread ends
+        std::thread::scope(|scope| {
+            while !shutdown::requested() {
+                match server.recv_timeout(POLL_INTERVAL) {
+                    Ok(Some(request)) => handle(scope, request, &db, dry_run, &resolving),
+                    Ok(None) => {}
+                    Err(e) => {
+                        log::error!("API request failed: {e}");
+                        shutdown::sleep(POLL_INTERVAL);
+                    }
+                }
+            }
+        });
+    }))
+}
+
+fn handle<'scope>(
+    scope: &'scope Scope<'scope, '_>,
+    mut request: Request,
+    db: &'scope Database,
+    dry_run: bool,
+    resolving: &'scope AtomicUsize,
+) {
+    let (config, add) = match check(&mut request) {
+        Action::Reply(status, body) => return reply(request, status, &body),
+        Action::Queue(config, add) => (config, add),
+    };
+    if resolving.fetch_add(1, Ordering::SeqCst) >= MAX_RESOLVING {
+        resolving.fetch_sub(1, Ordering::SeqCst);
+        return reply(request, 503, &json!({ "error": "too many requests" }));
+    }
+
+    scope.spawn(move || {
+        let (status, body) = queue_url(&config, db, &add, dry_run);
+        resolving.fetch_sub(1, Ordering::SeqCst);
+        reply(request, status, &body);
+    });
+}
+
+fn reply(request: Request, status: u16, body: &serde_json::Value) {
+    let mut response = Response::from_string(body.to_string()).with_status_code(status);
+    if let Ok(header) = Header::from_bytes("Content-Type", "application/json") {
+        response = response.with_header(header);
+    }
+    if let Err(e) = request.respond(response) {
+        log::warn!("Could not answer API request: {e}");
+    }
+}
+
+/// Route and authorize `request`, everything but queueing a URL is answered right away
+fn check(request: &mut Request) -> Action {
+    let queueing = match (request.url(), request.method()) {
+        ("/queue", Method::Post) => true,
+        ("/status", Method::Get) => false,
+        ("/queue" | "/status", _) => {
+            return Action::Reply(405, json!({ "error": "method not allowed" }))
+        }
+        _ => return Action::Reply(404, json!({ "error": "not found" })),
+    };
+
+    let Some(config) = CONFIG
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+    else {
+        return Action::Reply(503, json!({ "error": "not ready" }));
+    };
+    if let Some(token) = &config.hoard.api_token {
+        let expected = format!("Bearer {token}");
+        let authorized = request
+            .headers()
+            .iter()
+            .any(|header| header.field.equiv("Authorization") && header.value == expected);
+        if !authorized {
+            return Action::Reply(401, json!({ "error": "unauthorized" }));
+        }
+    }
+
+    if !queueing {
+        let modules: Vec<_> = supervisor::restart_counts()
+            .into_iter()
+            .map(|(name, restarts)| json!({ "name": name, "restarts": restarts }))
+            .collect();
+        return Action::Reply(200, json!({ "modules": modules }));
+    }
+
+    let is_json = request.headers().iter().any(|header| {
+        header.field.equiv("Content-Type")
+            && header
+                .value
+                .as_str()
+                .split(';')
+                .next()
+                .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"))
+    });
+    if !is_json {
+        return Action::Reply(
+            415,
+            json!({ "error": "expected Content-Type: application/json" }),
+        );
+    }
+
+    let mut body = String::new();
+    if let Err(e) = request.as_reader().take(MAX_BODY).read_to_string(&mut body) {
+        return Action::Reply(400, json!({ "error": e.to_string() }));
+    }
+    match serde_json::from_str(&body) {
+        Ok(add) => Action::Queue(config, add),
+        Err(e) => Action::Reply(400, json!({ "error": format!("invalid request: {e}") })),
+    }
+}
+
+/// Status code and JSON body of the response to `POST /queue`
+fn queue_url(
+    config: &GlobalConfig,
+    db: &Database,
+    add: &AddRequest,
+    dry_run: bool,
+) -> (u16, serde_json::Value) {
+    let dir = add.dir.as_deref().unwrap_or(queue::DEFAULT_DIR);
+    match queue::add(config, db, &add.url, &add.module, dir, dry_run) {
+        Ok(added) => {
+            log::info!(
+                "{} {} entries of {} for {}",
+                if dry_run { "Would queue" } else { "Queued" },
+                added.queued.len(),
+                add.url,
+                added.module
+            );
+            (
+                200,
+                json!({
+                    "module": added.module,
+                    "queued": added.queued,
+                    "downloaded": added.downloaded,
+                }),
+            )
+        }
+        Err(e) => (400, json!({ "error": e })),
+    }
+}
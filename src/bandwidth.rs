@@ -0,0 +1,264 @@
+//! Bandwidth limits for downloads.
+//!
+//! `[hoard] rate_limit` is a budget shared by all running downloads, a module's `rate_limit`
+//! is shared by the downloads of that module. Either can change with the time of day.
+//! Every running download gets an even share of each budget. When the shares change, because
+//! a download starts or ends or another time window begins, running downloads are restarted
+//! with their new share and continue where they stopped.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, PoisonError, RwLock},
+};
+
+use chrono::NaiveTime;
+use serde::{Deserialize, Serialize};
+
+use crate::schedule::TimeWindow;
+
+/// Key of the rate used outside of all time windows
+const DEFAULT_PROFILE: &str = "default";
+
+static GLOBAL: RwLock<Option<RateLimit>> = RwLock::new(None);
+/// Number of running downloads by module
+static RUNNING: Mutex<BTreeMap<String, usize>> = Mutex::new(BTreeMap::new());
+
+/// A download rate like `"5M"`, or rates by time of day like
+/// `{ "08:00-18:00" = "2M", "22:00-06:00" = "unlimited", default = "5M" }`.
+///
+/// Rates are bytes per second with an optional `K`, `M` or `G` suffix, or `unlimited`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RateLimit {
+    Fixed(String),
+    Profiles(BTreeMap<String, String>),
+}
+
+impl RateLimit {
+    /// The rate in bytes per second at `time`, `None` if unlimited.
+    ///
+    /// The first time window containing `time` applies, otherwise the `default` rate.
+    pub fn at(&self, time: NaiveTime) -> Result<Option<u64>, String> {
+        match self {
+            Self::Fixed(rate) => parse_rate(rate),
+            Self::Profiles(profiles) => {
+                for (window, rate) in profiles {
+                    if window != DEFAULT_PROFILE && window.parse::<TimeWindow>()?.contains(time) {
+                        return parse_rate(rate);
+                    }
+                }
+                profiles
+                    .get(DEFAULT_PROFILE)
+                    .map_or(Ok(None), |rate| parse_rate(rate))
+            }
+        }
+    }
+
+    /// Check all rates and time windows
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Self::Fixed(rate) => parse_rate(rate).map(|_| ()),
+            Self::Profiles(profiles) => profiles.iter().try_for_each(|(window, rate)| {
+                if window != DEFAULT_PROFILE {
+                    window.parse::<TimeWindow>()?;
+                }
+                parse_rate(rate).map(|_| ())
+            }),
+        }
+    }
+}
+
+/// Parse a rate like `"500K"` or `"1.5M"` into bytes per second, `None` if unlimited
+pub fn parse_rate(rate: &str) -> Result<Option<u64>, String> {
+    let rate = rate.trim();
+    if rate.eq_ignore_ascii_case("unlimited") {
+        return Ok(None);
+    }
+
+    let (number, factor) = match rate.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&rate[..i], 1 << 10),
+        Some((i, 'M' | 'm')) => (&rate[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&rate[..i], 1 << 30),
+        _ => (rate, 1),
+    };
+    match number.parse::<f64>() {
+        Ok(number) if number > 0.0 => Ok(Some((number * f64::from(factor)) as u64)),
+        _ => Err(format!(
+            "invalid rate \"{rate}\", expected bytes per second like \"5M\" or \"unlimited\""
+        )),
+    }
+}
+
+/// Set the budget shared by all downloads, `None` for no limit
+pub fn set_global(limit: Option<RateLimit>) {
+    *GLOBAL.write().unwrap_or_else(PoisonError::into_inner) = limit;
+}
+
+/// A running download of a module, counted for the shares until dropped
+pub struct Running {
+    module: String,
+}
+
+impl Running {
+    pub fn start(module: &str) -> Self {
+        *RUNNING
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(module.to_string())
+            .or_default() += 1;
+        Self {
+            module: module.to_string(),
+        }
+    }
+
+    /// The rate for this download right now in bytes per second, `None` if unlimited.
+    ///
+    /// The global budget is split between all running downloads, the budget of the module
+    /// between its own running downloads. The smaller share applies.
+    pub fn share(&self, module: Option<&RateLimit>) -> Option<u64> {
+        let running = RUNNING
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        let global = GLOBAL
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        share(
+            global.as_ref(),
+            module,
+            &running,
+            &self.module,
+            chrono::Local::now().time(),
+        )
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        let mut running = RUNNING.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(count) = running.get_mut(&self.module) {
+            *count -= 1;
+            if *count == 0 {
+                running.remove(&self.module);
+            }
+        }
+    }
+}
+
+/// The share of a download of `module` at `time`, see [`Running::share`]
+fn share(
+    global: Option<&RateLimit>,
+    module_limit: Option<&RateLimit>,
+    running: &BTreeMap<String, usize>,
+    module: &str,
+    time: NaiveTime,
+) -> Option<u64> {
+    let rate = |limit: &RateLimit| {
+        limit.at(time).unwrap_or_else(|e| {
+            log::error!("Ignoring rate limit: {e}");
+            None
+        })
+    };
+
+    let downloads = |count: usize| count.max(1) as u64;
+    let global = global
+        .and_then(rate)
+        .map(|rate| rate / downloads(running.values().sum()));
+    let module = module_limit
+        .and_then(rate)
+        .map(|rate| rate / downloads(running.get(module).copied().unwrap_or(0)));
+
+    match (global, module) {
+        (Some(global), Some(module)) => Some(global.min(module)),
+        (global, module) => global.or(module),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn rates() {
+        assert_eq!(parse_rate("500"), Ok(Some(500)));
+        assert_eq!(parse_rate("500K"), Ok(Some(500 << 10)));
+        assert_eq!(parse_rate("5m"), Ok(Some(5 << 20)));
+        assert_eq!(parse_rate(" 1.5M "), Ok(Some(3 << 19)));
+        assert_eq!(parse_rate("2G"), Ok(Some(2 << 30)));
+        assert_eq!(parse_rate("unlimited"), Ok(None));
+        assert_eq!(parse_rate("Unlimited"), Ok(None));
+
+        for invalid in ["", "M", "0", "-1K", "5T", "fast"] {
+            assert!(parse_rate(invalid).is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn profiles() {
+        let limit = RateLimit::Profiles(BTreeMap::from([
+            ("08:00-18:00".to_string(), "2M".to_string()),
+            ("22:00-06:00".to_string(), "unlimited".to_string()),
+            (DEFAULT_PROFILE.to_string(), "5M".to_string()),
+        ]));
+        assert_eq!(limit.at(time(12, 0)), Ok(Some(2 << 20)));
+        assert_eq!(limit.at(time(23, 30)), Ok(None));
+        assert_eq!(limit.at(time(3, 0)), Ok(None));
+        assert_eq!(limit.at(time(7, 0)), Ok(Some(5 << 20)));
+        assert_eq!(limit.at(time(20, 0)), Ok(Some(5 << 20)));
+        assert!(limit.validate().is_ok());
+
+        let without_default = RateLimit::Profiles(BTreeMap::from([(
+            "08:00-18:00".to_string(),
+            "2M".to_string(),
+        )]));
+        assert_eq!(without_default.at(time(20, 0)), Ok(None));
+
+        assert_eq!(
+            RateLimit::Fixed("1K".to_string()).at(time(20, 0)),
+            Ok(Some(1024))
+        );
+        assert!(
+            RateLimit::Profiles(BTreeMap::from([("8-18".to_string(), "2M".to_string())]))
+                .validate()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn shares() {
+        let global = RateLimit::Profiles(BTreeMap::from([
+            ("08:00-18:00".to_string(), "3M".to_string()),
+            (DEFAULT_PROFILE.to_string(), "unlimited".to_string()),
+        ]));
+        let module = RateLimit::Fixed("1M".to_string());
+        let running = BTreeMap::from([("a".to_string(), 2), ("b".to_string(), 1)]);
+        let day = time(12, 0);
+
+        // three downloads share the global budget
+        assert_eq!(
+            share(Some(&global), None, &running, "b", day),
+            Some(1 << 20)
+        );
+        // two downloads of `a` share its budget, which is smaller
+        assert_eq!(
+            share(Some(&global), Some(&module), &running, "a", day),
+            Some(1 << 19)
+        );
+        // outside of the window only the module limits
+        assert_eq!(
+            share(Some(&global), Some(&module), &running, "a", time(20, 0)),
+            Some(1 << 19)
+        );
+        assert_eq!(share(Some(&global), None, &running, "a", time(20, 0)), None);
+
+        // once the others finished, one download gets the whole budget
+        let alone = BTreeMap::from([("b".to_string(), 1)]);
+        assert_eq!(share(Some(&global), None, &alone, "b", day), Some(3 << 20));
+        assert_eq!(share(None, None, &alone, "b", day), None);
+    }
+}
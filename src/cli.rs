@@ -0,0 +1,481 @@
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use clap::{Parser, Subcommand};
+use serde::Serialize;
+
+use crate::{
+    api,
+    bandwidth::{self, RateLimit},
+    build_modules,
+    config::{secrets, GlobalConfig},
+    db::{Database, DatabaseBackend},
+    ensure_dir_exists, module_specs, queue,
+    reload::Reloader,
+    shutdown, slots,
+    supervisor::Supervisor,
+    yt_dlp::{
+        config::Item,
+        entry::{media_id_from_url, Entry},
+        resolve_url,
+    },
+};
+
+/// Default location of the download database
+const DEFAULT_DB: &str = "data/download.db";
+
+/// Hoard media from many sources
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Args {
+    /// Path to the config file
+    #[arg(
+        short,
+        long,
+        global = true,
+        env = "HOARD_CONFIG",
+        default_value = "config.toml"
+    )]
+    pub config: PathBuf,
+    /// Path to the download database, overrides `db` in the config file
+    #[arg(long, global = true, env = "HOARD_DB")]
+    pub db: Option<PathBuf>,
+    /// Only report what would be downloaded
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// Run the download daemon (default)
+    Daemon,
+    /// List completed downloads
+    List {
+        /// Only list downloads of this module
+        #[arg(long)]
+        module: Option<String>,
+        /// Only list downloads of this item
+        #[arg(long)]
+        item: Option<String>,
+    },
+    /// Show an overview of the archive
+    Status,
+    /// Check the config file for errors
+    CheckConfig,
+    /// Print the fully resolved options of every module and item
+    ShowConfig,
+    /// Mark a URL as downloaded without downloading it
+    MarkDownloaded { url: String },
+    /// Forget a downloaded URL so it will be downloaded again
+    Forget { url: String },
+    /// Queue a URL for download by a module, a playlist queues all of its entries
+    Add {
+        url: String,
+        /// Module to download with, using its options
+        #[arg(long)]
+        module: String,
+        /// Directory relative to the module directory
+        #[arg(long, default_value = queue::DEFAULT_DIR)]
+        dir: String,
+    },
+    /// Check every module once and exit, fails if any fetch or download failed
+    RunOnce {
+        /// Only run this module
+        module: Option<String>,
+        /// Only check this item (name or `category/name`)
+        item: Option<String>,
+    },
+}
+
+impl Args {
+    /// The database location from the command line, the config or the default in that order
+    pub fn db_path(&self, config: &GlobalConfig) -> PathBuf {
+        self.db
+            .clone()
+            .or_else(|| config.hoard.db.clone())
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_DB))
+    }
+
+    /// Open the database and start its backend thread.
+    ///
+    /// The thread finishes once all handles to the database are dropped.
+    fn open_db(&self, config: &GlobalConfig) -> Result<(Database, JoinHandle<()>), String> {
+        let db_path = self.db_path(config);
+        if let Some(parent) = db_path.parent() {
+            ensure_dir_exists(&parent.to_path_buf());
+        }
+
+        let backend = DatabaseBackend::new(&db_path.to_string_lossy())
+            .map_err(|e| format!("Could not open database {}: {e}", db_path.display()))?;
+        let db = backend.take_db();
+        let thread = std::thread::spawn(move || {
+            backend.run();
+        });
+
+        Ok((db, thread))
+    }
+
+    /// Run the selected command
+    pub fn run(&self) -> ExitCode {
+        let mut config = match GlobalConfig::load(&self.config) {
+            Ok(config) => config,
+            Err(e) => {
+                log::error!("{e}");
+                eprintln!("{e}");
+                return ExitCode::FAILURE;
+            }
+        };
+
+        if self.dry_run {
+            config.set_dry_run();
+        }
+
+        let command = self.command.clone().unwrap_or(Command::Daemon);
+        match command {
+            Command::CheckConfig => {
+                println!("{} is valid", self.config.display());
+                return ExitCode::SUCCESS;
+            }
+            Command::ShowConfig => return show_config(&config),
+            _ => {}
+        }
+
+        let (db, db_thread) = match self.open_db(&config) {
+            Ok(db) => db,
+            Err(e) => {
+                log::error!("{e}");
+                eprintln!("{e}");
+                return ExitCode::FAILURE;
+            }
+        };
+
+        if let Command::Daemon | Command::RunOnce { .. } = command {
+            if let Err(e) = shutdown::install_handler() {
+                log::error!("{e}");
+            }
+            if let Some(grace_period) = config.hoard.shutdown_grace_period {
+                shutdown::set_grace_period(Duration::from_secs(grace_period));
+            }
+            slots::set_limit(config.hoard.max_parallel_downloads);
+            bandwidth::set_global(config.hoard.rate_limit.clone());
+        }
+
+        let code = match command {
+            Command::CheckConfig | Command::ShowConfig => {
+                unreachable!("handled before opening the database")
+            }
+            Command::Daemon => daemon(config, &db, &self.config, self.dry_run),
+            Command::RunOnce { module, item } => {
+                run_once(config, &db, module.as_deref(), item.as_deref())
+            }
+            Command::List { module, item } => list(&db, module.as_deref(), item.as_deref()),
+            Command::Status => status(&db),
+            Command::MarkDownloaded { url } => mark_downloaded(&db, &url),
+            Command::Forget { url } => forget(&db, &url),
+            Command::Add { url, module, dir } => {
+                add(&config, &db, &url, &module, &dir, self.dry_run)
+            }
+        };
+
+        // let the backend finish all queued queries before exiting
+        drop(db);
+        if db_thread.join().is_err() {
+            log::error!("Database thread panicked");
+            return ExitCode::FAILURE;
+        }
+        code
+    }
+}
+
+fn daemon(config: GlobalConfig, db: &Database, path: &Path, dry_run: bool) -> ExitCode {
+    log::info!("Starting hoard");
+    ensure_dir_exists(&config.hoard.data_dir);
+    db.recover_queue();
+
+    let api = config.hoard.api.as_deref().and_then(|address| {
+        api::spawn(address, &config, db.clone(), dry_run)
+            .map_err(|e| log::error!("{e}"))
+            .ok()
+    });
+
+    let mut supervisor = Supervisor::new();
+    Reloader::new(path.to_path_buf(), config, db.clone(), dry_run).run(&mut supervisor);
+
+    supervisor.join();
+    if let Some(api) = api {
+        let _ = api.join();
+    }
+    if shutdown::requested() {
+        log::info!("All modules stopped");
+    }
+    ExitCode::SUCCESS
+}
+
+fn run_once(
+    config: GlobalConfig,
+    db: &Database,
+    module: Option<&str>,
+    item: Option<&str>,
+) -> ExitCode {
+    ensure_dir_exists(&config.hoard.data_dir);
+    db.recover_queue();
+
+    let modules: Vec<_> = build_modules(&config, db)
+        .into_iter()
+        .filter(|m| module.is_none_or(|name| m.name().eq_ignore_ascii_case(name)))
+        .collect();
+    if modules.is_empty() {
+        eprintln!("No module called \"{}\"", module.unwrap_or_default());
+        return ExitCode::FAILURE;
+    }
+
+    let threads: Vec<_> = modules
+        .into_iter()
+        .map(|module| {
+            let item = item.map(str::to_string);
+            std::thread::spawn(move || match item {
+                Some(item) => module.run_item(&item),
+                None => Some(module.run_cycle()),
+            })
+        })
+        .collect();
+    // a panicking module counts as failed as well, modules without the item are left out
+    let results: Vec<bool> = threads
+        .into_iter()
+        .filter_map(|thread| thread.join().unwrap_or(Some(false)))
+        .collect();
+    if let (Some(item), true) = (item, results.is_empty()) {
+        eprintln!("No item called \"{item}\"");
+        return ExitCode::FAILURE;
+    }
+
+    if results.into_iter().all(|ok| ok) && !shutdown::requested() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Fully resolved config of a module, printed by `show-config`
+#[derive(Serialize)]
+struct EffectiveModule {
+    name: String,
+    dir: PathBuf,
+    interval: Option<u64>,
+    schedule: Option<String>,
+    concurrency: usize,
+    priority: u32,
+    rate_limit: Option<RateLimit>,
+    dry_run: bool,
+    webhooks: Vec<String>,
+    items: Vec<Item>,
+}
+
+fn show_config(config: &GlobalConfig) -> ExitCode {
+    #[derive(Serialize)]
+    struct EffectiveConfig {
+        module: Vec<EffectiveModule>,
+    }
+
+    let module = module_specs(config)
+        .into_iter()
+        .map(|spec| {
+            let yt_dlp = spec.config.yt_dlp_config();
+            let mut items: Vec<Item> = yt_dlp.items().into_iter().flatten().collect();
+            for item in &mut items {
+                item.dir = spec.root_dir.join(&item.dir);
+                item.options = item.options.resolved();
+            }
+            items.sort_by(|a, b| a.dir.cmp(&b.dir));
+
+            EffectiveModule {
+                name: yt_dlp.name.unwrap_or_else(|| "yt-dlp".to_string()),
+                dir: spec.root_dir,
+                interval: yt_dlp.interval,
+                schedule: yt_dlp.options.schedule.clone(),
+                concurrency: yt_dlp.concurrency.unwrap_or(1),
+                priority: yt_dlp.priority.unwrap_or(0),
+                rate_limit: yt_dlp.rate_limit,
+                dry_run: yt_dlp.dry_run.unwrap_or(false),
+                webhooks: yt_dlp.webhooks.unwrap_or_default(),
+                items,
+            }
+        })
+        .collect();
+
+    match toml::to_string(&EffectiveConfig { module }) {
+        Ok(effective) => {
+            print!("{}", secrets::redact(&effective));
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Could not print config: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn list(db: &Database, module: Option<&str>, item: Option<&str>) -> ExitCode {
+    for record in db.list_downloads(module, item) {
+        let download = &record.download;
+        println!(
+            "{}  {}/{}  {}  ({})  {}",
+            record.timestamp,
+            download.module,
+            download.item,
+            download.entry.title,
+            format_size(download.size),
+            download.entry.url
+        );
+    }
+    ExitCode::SUCCESS
+}
+
+fn status(db: &Database) -> ExitCode {
+    let Some(status) = db.status() else {
+        return ExitCode::FAILURE;
+    };
+
+    println!("Downloaded URLs:  {}", status.urls);
+    println!(
+        "Downloads:        {} ({})",
+        status.downloads,
+        format_size(status.size)
+    );
+    println!("Queued:           {}", status.queued);
+    println!("Waiting to retry: {}", status.retrying);
+    println!("Failed:           {}", status.failed.len());
+
+    if !status.modules.is_empty() {
+        println!();
+        for module in &status.modules {
+            println!(
+                "{}: {} downloads ({}), last at {}",
+                module.module,
+                module.downloads,
+                format_size(module.size),
+                module.last_download.as_deref().unwrap_or("never")
+            );
+        }
+    }
+
+    if !status.failed.is_empty() {
+        println!();
+        println!("Failed downloads:");
+        for failed in &status.failed {
+            println!(
+                "{}/{}  {}  ({} attempts, {})  {}",
+                failed.module,
+                failed.item,
+                failed.title,
+                failed.attempts,
+                failed.error.kind(),
+                failed.url
+            );
+        }
+    }
+
+    ExitCode::SUCCESS
+}
+
+fn mark_downloaded(db: &Database, url: &str) -> ExitCode {
+    let entry = match resolve_url(url) {
+        Ok(entry) => entry,
+        Err(e) => {
+            log::warn!("Could not resolve {url}: {e}");
+            let Some((extractor, id)) = media_id_from_url(url) else {
+                eprintln!("Could not resolve {url}: {e}");
+                return ExitCode::FAILURE;
+            };
+            Entry {
+                id,
+                extractor,
+                title: url.to_string(),
+                url: url.to_string(),
+                upload_date: None,
+                duration: None,
+                live_status: None,
+                availability: None,
+            }
+        }
+    };
+
+    if db.check_for_url(&entry) {
+        println!("\"{}\" is already downloaded", entry.title);
+    } else {
+        db.insert_url(&entry);
+        db.clear_attempt(&entry);
+        println!("Marked \"{}\" as downloaded", entry.title);
+    }
+    ExitCode::SUCCESS
+}
+
+fn forget(db: &Database, url: &str) -> ExitCode {
+    let identity = media_id_from_url(url).or_else(|| {
+        resolve_url(url)
+            .ok()
+            .map(|entry| (entry.extractor, entry.id))
+    });
+
+    match db.forget(url, identity) {
+        0 => {
+            eprintln!("{url} is not downloaded");
+            ExitCode::FAILURE
+        }
+        count => {
+            println!("Forgot {count} download(s) of {url}");
+            ExitCode::SUCCESS
+        }
+    }
+}
+
+fn add(
+    config: &GlobalConfig,
+    db: &Database,
+    url: &str,
+    module: &str,
+    dir: &str,
+    dry_run: bool,
+) -> ExitCode {
+    let added = match queue::add(config, db, url, module, dir, dry_run) {
+        Ok(added) => added,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let verb = if dry_run { "Would queue" } else { "Queued" };
+    for entry in &added.queued {
+        println!(
+            "[{}] {verb} \"{}\" ({})",
+            added.module, entry.title, entry.url
+        );
+    }
+    for entry in &added.downloaded {
+        println!("\"{}\" is already downloaded", entry.title);
+    }
+    ExitCode::SUCCESS
+}
+
+/// Human readable size in binary units
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
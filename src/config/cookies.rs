@@ -0,0 +1,146 @@
+//! Relative `cookie` paths in the config.
+//!
+//! `yt-dlp` runs in the directory of the item it downloads, so a relative cookie file is
+//! resolved against the directory of the config file which set it when the config is loaded.
+
+use std::path::Path;
+
+use super::{include::Origins, validate::key};
+use crate::yt_dlp::config::is_item;
+
+/// Make every relative `cookie` in the raw config absolute.
+///
+/// Cookies from included files are relative to that file, all others to `config_path`.
+pub fn absolute(config: &mut toml::Table, config_path: &Path, origins: &Origins) {
+    let resolve = |path: &str, cookie: &mut String| {
+        if Path::new(cookie.as_str()).is_absolute() {
+            return;
+        }
+        let file = origins.file(path).unwrap_or(config_path);
+        let dir = file.parent().unwrap_or_else(|| Path::new(""));
+        let dir = std::path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf());
+        *cookie = dir.join(cookie.as_str()).to_string_lossy().into_owned();
+    };
+
+    if let Some(toml::Value::Table(defaults)) = config.get_mut("defaults") {
+        options(defaults, "defaults", &resolve);
+    }
+    if let Some(toml::Value::Table(presets)) = config.get_mut("presets") {
+        for (name, preset) in presets {
+            if let toml::Value::Table(preset) = preset {
+                options(preset, &format!("presets.{}", key(name)), &resolve);
+            }
+        }
+    }
+    for (name, items_key) in [("youtube", "channels"), ("soundcloud", "artists")] {
+        if let Some(toml::Value::Table(module)) = config.get_mut(name) {
+            self::module(module, name, items_key, &resolve);
+        }
+    }
+    if let Some(toml::Value::Array(modules)) = config.get_mut("yt_dlp") {
+        for (i, module) in modules.iter_mut().enumerate() {
+            if let toml::Value::Table(module) = module {
+                self::module(module, &format!("yt_dlp[{i}]"), "items", &resolve);
+            }
+        }
+    }
+}
+
+fn module(
+    module: &mut toml::Table,
+    path: &str,
+    items_key: &str,
+    resolve: &impl Fn(&str, &mut String),
+) {
+    options(module, path, resolve);
+    if let Some(toml::Value::Table(items)) = module.get_mut(items_key) {
+        self::items(items, &format!("{path}.{items_key}"), resolve);
+    }
+}
+
+fn items(items: &mut toml::Table, path: &str, resolve: &impl Fn(&str, &mut String)) {
+    for (name, value) in items {
+        let path = format!("{path}.{}", key(name));
+        match value {
+            toml::Value::Table(item) if is_item(item) => options(item, &path, resolve),
+            toml::Value::Table(category) => self::items(category, &path, resolve),
+            _ => {}
+        }
+    }
+}
+
+fn options(table: &mut toml::Table, path: &str, resolve: &impl Fn(&str, &mut String)) {
+    if let Some(toml::Value::String(cookie)) = table.get_mut("cookie") {
+        resolve(&format!("{path}.cookie"), cookie);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_to_the_config() {
+        let mut config: toml::Table = toml::from_str(
+            r#"
+            [defaults]
+            cookie = "cookies.txt"
+            [presets.members]
+            cookie = "/etc/hoard/members.txt"
+            [[yt_dlp]]
+            name = "a"
+            [yt_dlp.items.Music]
+            Deep = { url = "https://example.com/deep", cookie = "music/cookies.txt" }
+            Plain = "https://example.com/plain"
+            "#,
+        )
+        .unwrap();
+        absolute(
+            &mut config,
+            Path::new("/srv/hoard/config.toml"),
+            &Origins::default(),
+        );
+
+        assert_eq!(
+            config["defaults"]["cookie"].as_str(),
+            Some("/srv/hoard/cookies.txt")
+        );
+        assert_eq!(
+            config["presets"]["members"]["cookie"].as_str(),
+            Some("/etc/hoard/members.txt")
+        );
+        assert_eq!(
+            config["yt_dlp"][0]["items"]["Music"]["Deep"]["cookie"].as_str(),
+            Some("/srv/hoard/music/cookies.txt")
+        );
+    }
+
+    #[test]
+    fn relative_to_the_included_file() {
+        let mut config: toml::Table = toml::from_str(
+            r#"
+            [[yt_dlp]]
+            name = "a"
+            cookie = "main.txt"
+            [yt_dlp.items]
+            Included = { url = "https://example.com/included", cookie = "included.txt" }
+            "#,
+        )
+        .unwrap();
+        let mut origins = Origins::default();
+        origins.insert(
+            "yt_dlp[0].items.Included".to_string(),
+            Path::new("/srv/hoard/conf.d/music.toml"),
+        );
+        absolute(&mut config, Path::new("/srv/hoard/config.toml"), &origins);
+
+        assert_eq!(
+            config["yt_dlp"][0]["cookie"].as_str(),
+            Some("/srv/hoard/main.txt")
+        );
+        assert_eq!(
+            config["yt_dlp"][0]["items"]["Included"]["cookie"].as_str(),
+            Some("/srv/hoard/conf.d/included.txt")
+        );
+    }
+}
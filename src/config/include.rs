@@ -0,0 +1,420 @@
+//! Config split across several files with `[hoard] include = ["conf.d/*.toml"]`.
+//!
+//! Included files are merged into the main config. They can add modules, items and presets,
+//! and add items to modules of other files by repeating a `[[yt_dlp]]` block with the same `name`.
+//! Setting the same value in two files is a conflict.
+
+use std::path::{Path, PathBuf};
+
+use super::validate::key;
+
+/// Which included file each part of the merged config came from
+#[derive(Debug, Default)]
+pub struct Origins(Vec<(String, PathBuf)>);
+
+impl Origins {
+    pub(super) fn insert(&mut self, path: String, file: &Path) {
+        self.0.push((path, file.to_path_buf()));
+    }
+
+    /// The file which set `path`, if it was not the main config
+    pub fn file(&self, path: &str) -> Option<&Path> {
+        self.0
+            .iter()
+            .filter(|(origin, _)| {
+                path.strip_prefix(origin.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with(['.', '[', ':']))
+            })
+            .max_by_key(|(origin, _)| origin.len())
+            .map(|(_, file)| file.as_path())
+    }
+
+    /// Prefix an error `path: message` with the included file it is about
+    pub fn locate(&self, error: String) -> String {
+        match self.file(&error) {
+            Some(file) => format!("{}: {error}", file.display()),
+            None => error,
+        }
+    }
+}
+
+/// Files matched by the `include` patterns, relative to the directory of the main config
+pub fn files(config_path: &Path, patterns: &[String]) -> Result<Vec<PathBuf>, String> {
+    let dir = config_path.parent().unwrap_or_else(|| Path::new(""));
+    let mut files = Vec::new();
+    for pattern in patterns {
+        let pattern = dir.join(pattern);
+        let matches = glob::glob(&pattern.to_string_lossy())
+            .map_err(|e| format!("hoard.include: invalid pattern \"{pattern:?}\": {e}"))?;
+        for file in matches {
+            let file = file.map_err(|e| format!("hoard.include: {e}"))?;
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// The `include` patterns of a raw config
+pub fn patterns(config: &toml::Table) -> Vec<String> {
+    let Some(toml::Value::Table(hoard)) = config.get("hoard") else {
+        return Vec::new();
+    };
+    match hoard.get("include") {
+        Some(toml::Value::Array(patterns)) => patterns
+            .iter()
+            .filter_map(|pattern| pattern.as_str().map(ToString::to_string))
+            .collect(),
+        Some(toml::Value::String(pattern)) => vec![pattern.clone()],
+        _ => Vec::new(),
+    }
+}
+
+/// Merge all included files into `config`.
+///
+/// # Returns
+///
+/// Where the merged parts came from, and every problem found with its file.
+pub fn include(config_path: &Path, config: &mut toml::Table) -> (Origins, Vec<String>) {
+    let mut origins = Origins::default();
+    let mut errors = Vec::new();
+
+    let files = match files(config_path, &patterns(config)) {
+        Ok(files) => files,
+        Err(e) => return (origins, vec![e]),
+    };
+
+    for file in files {
+        let included: toml::Table = match std::fs::read_to_string(&file)
+            .map_err(|e| e.to_string())
+            .and_then(|content| toml::from_str(&content).map_err(|e| e.to_string()))
+        {
+            Ok(included) => included,
+            Err(e) => {
+                errors.push(format!("{}: {}", file.display(), e.trim_end()));
+                continue;
+            }
+        };
+
+        let mut merge = Merge {
+            file: &file,
+            origins: &mut origins,
+            errors: &mut errors,
+        };
+        merge.config(config, included);
+    }
+
+    (origins, errors)
+}
+
+/// Merges one included file
+struct Merge<'a> {
+    file: &'a Path,
+    origins: &'a mut Origins,
+    errors: &'a mut Vec<String>,
+}
+
+impl Merge<'_> {
+    fn config(&mut self, config: &mut toml::Table, included: toml::Table) {
+        for (name, value) in included {
+            match (name.as_str(), value) {
+                ("hoard", toml::Value::Table(hoard)) if hoard.contains_key("include") => {
+                    self.error(
+                        "hoard.include",
+                        "included files can not include further files",
+                    );
+                }
+                ("yt_dlp", toml::Value::Array(modules)) => self.modules(config, modules),
+                (_, value) => self.value(config, "", name, value),
+            }
+        }
+    }
+
+    /// Add `[[yt_dlp]]` modules, or merge them into the module with the same name
+    fn modules(&mut self, config: &mut toml::Table, modules: Vec<toml::Value>) {
+        let existing = config
+            .entry("yt_dlp")
+            .or_insert_with(|| toml::Value::Array(Vec::new()));
+        let toml::Value::Array(existing) = existing else {
+            self.error("yt_dlp", "expected an array of modules");
+            return;
+        };
+
+        for module in modules {
+            let same_name = module.get("name").and_then(|name| {
+                existing
+                    .iter()
+                    .position(|other| other.get("name") == Some(name))
+            });
+
+            match (same_name, module) {
+                (Some(i), toml::Value::Table(module)) => {
+                    let path = format!("yt_dlp[{i}]");
+                    if let toml::Value::Table(target) = &mut existing[i] {
+                        for (name, value) in module {
+                            if name == "name" {
+                                continue;
+                            }
+                            self.value(target, &path, name, value);
+                        }
+                    }
+                }
+                (_, module) => {
+                    self.origins
+                        .insert(format!("yt_dlp[{}]", existing.len()), self.file);
+                    existing.push(module);
+                }
+            }
+        }
+    }
+
+    /// Merge `value` into `table` as `name`, tables are merged recursively
+    fn value(&mut self, table: &mut toml::Table, path: &str, name: String, value: toml::Value) {
+        let path = if path.is_empty() {
+            key(&name)
+        } else {
+            format!("{path}.{}", key(&name))
+        };
+
+        match (table.get_mut(&name), value) {
+            (None, value) => {
+                self.origins.insert(path, self.file);
+                table.insert(name, value);
+            }
+            (Some(toml::Value::Table(target)), toml::Value::Table(value)) => {
+                for (name, value) in value {
+                    self.value(target, &path, name, value);
+                }
+            }
+            (Some(_), _) => {
+                let other = self.origins.file(&path).map_or_else(
+                    || "the main config".to_string(),
+                    |f| f.display().to_string(),
+                );
+                self.error(&path, format!("already set in {other}"));
+            }
+        }
+    }
+
+    fn error(&mut self, path: &str, message: impl std::fmt::Display) {
+        self.errors
+            .push(format!("{}: {path}: {message}", self.file.display()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write `files` into a fresh temporary directory, returns the path of the first one
+    fn write(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hoard-include-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (file, content) in files {
+            let file = dir.join(file);
+            std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+            std::fs::write(file, content).unwrap();
+        }
+        dir.join(files[0].0)
+    }
+
+    fn load(path: &Path) -> (toml::Table, Origins, Vec<String>) {
+        let mut config = toml::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        let (origins, errors) = include(path, &mut config);
+        (config, origins, errors)
+    }
+
+    #[test]
+    fn adds_items_to_modules_of_the_main_file() {
+        let path = write(
+            "items",
+            &[
+                (
+                    "config.toml",
+                    r#"
+                    [hoard]
+                    include = ["conf.d/*.toml"]
+                    [[yt_dlp]]
+                    name = "Music"
+                    interval = 60
+                    [yt_dlp.items]
+                    Main = "https://example.com/main"
+                    "#,
+                ),
+                (
+                    "conf.d/music.toml",
+                    r#"
+                    [[yt_dlp]]
+                    name = "Music"
+                    [yt_dlp.items]
+                    Included = "https://example.com/included"
+                    [[yt_dlp]]
+                    name = "Videos"
+                    interval = 30
+                    [presets.audio]
+                    audio_only = true
+                    "#,
+                ),
+            ],
+        );
+        let (config, origins, errors) = load(&path);
+        assert!(errors.is_empty(), "{errors:?}");
+
+        let modules = config["yt_dlp"].as_array().unwrap();
+        assert_eq!(modules.len(), 2);
+        let items = modules[0]["items"].as_table().unwrap();
+        assert!(items.contains_key("Main") && items.contains_key("Included"));
+        assert_eq!(modules[1]["name"].as_str(), Some("Videos"));
+        assert_eq!(
+            config["presets"]["audio"]["audio_only"].as_bool(),
+            Some(true)
+        );
+
+        let included = path.parent().unwrap().join("conf.d/music.toml");
+        assert_eq!(
+            origins.file("yt_dlp[0].items.Included"),
+            Some(included.as_path())
+        );
+        assert_eq!(origins.file("yt_dlp[1].interval"), Some(included.as_path()));
+        assert_eq!(origins.file("yt_dlp[0].items.Main"), None);
+        assert_eq!(origins.file("yt_dlp[0].interval"), None);
+    }
+
+    #[test]
+    fn conflicts() {
+        let path = write(
+            "conflicts",
+            &[
+                (
+                    "config.toml",
+                    r#"
+                    [hoard]
+                    data_dir = "data"
+                    include = ["a.toml", "b.toml"]
+                    [[yt_dlp]]
+                    name = "Music"
+                    interval = 60
+                    "#,
+                ),
+                (
+                    "a.toml",
+                    r#"
+                    [hoard]
+                    data_dir = "elsewhere"
+                    [defaults]
+                    limit = 5
+                    [[yt_dlp]]
+                    name = "Music"
+                    interval = 30
+                    "#,
+                ),
+                (
+                    "b.toml",
+                    r#"
+                    [defaults]
+                    limit = 10
+                    "#,
+                ),
+            ],
+        );
+        let (config, _, errors) = load(&path);
+        let dir = path.parent().unwrap();
+        let a = dir.join("a.toml");
+        let b = dir.join("b.toml");
+        assert_eq!(
+            errors,
+            [
+                format!(
+                    "{}: hoard.data_dir: already set in the main config",
+                    a.display()
+                ),
+                format!(
+                    "{}: yt_dlp[0].interval: already set in the main config",
+                    a.display()
+                ),
+                format!(
+                    "{}: defaults.limit: already set in {}",
+                    b.display(),
+                    a.display()
+                ),
+            ]
+        );
+        // the first value is kept
+        assert_eq!(config["hoard"]["data_dir"].as_str(), Some("data"));
+        assert_eq!(config["defaults"]["limit"].as_integer(), Some(5));
+    }
+
+    #[test]
+    fn no_nested_includes() {
+        let path = write(
+            "nested",
+            &[
+                ("config.toml", "[hoard]\ninclude = [\"a.toml\"]\n"),
+                ("a.toml", "[hoard]\ninclude = [\"b.toml\"]\n"),
+                ("b.toml", "[defaults]\nlimit = 5\n"),
+            ],
+        );
+        let (config, _, errors) = load(&path);
+        let a = path.parent().unwrap().join("a.toml");
+        assert_eq!(
+            errors,
+            [format!(
+                "{}: hoard.include: included files can not include further files",
+                a.display()
+            )]
+        );
+        assert!(!config.contains_key("defaults"));
+    }
+
+    #[test]
+    fn errors_point_at_the_included_file() {
+        let path = write(
+            "locate",
+            &[
+                (
+                    "config.toml",
+                    r#"
+                    [hoard]
+                    data_dir = "data"
+                    include = ["music.toml"]
+                    [[yt_dlp]]
+                    name = "Videos"
+                    interval = 60
+                    items = {}
+                    "#,
+                ),
+                (
+                    "music.toml",
+                    r#"
+                    [[yt_dlp]]
+                    name = "Music"
+                    interval = 0
+                    [yt_dlp.items]
+                    Bad = "not a url"
+                    "#,
+                ),
+            ],
+        );
+        let error = crate::config::GlobalConfig::load(&path).unwrap_err();
+        let music = path.parent().unwrap().join("music.toml");
+        assert!(
+            error.contains(&format!(
+                "{}: yt_dlp[1].interval: must be greater than 0",
+                music.display()
+            )),
+            "{error}"
+        );
+        assert!(
+            error.contains(&format!("{}: yt_dlp[1].items.Bad: ", music.display())),
+            "{error}"
+        );
+
+        let (_, origins, _) = load(&path);
+        assert_eq!(
+            origins.locate("yt_dlp[0].interval: must be greater than 0".to_string()),
+            "yt_dlp[0].interval: must be greater than 0"
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bandwidth::RateLimit,
+    yt_dlp::config::{DownloadOptions, YtDlpConfig},
+};
+
+pub mod cookies;
+pub mod include;
+pub mod presets;
+pub mod secrets;
+pub mod validate;
+
+/// General settings for hoard
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HoardConfig {
+    /// Top level data download directory
+    pub data_dir: PathBuf,
+    /// Location of the download database
+    pub db: Option<PathBuf>,
+    /// Seconds running downloads may continue after `SIGTERM` or `SIGINT`
+    pub shutdown_grace_period: Option<u64>,
+    /// Further config files to merge in, glob patterns relative to this file
+    pub include: Option<Vec<String>>,
+    /// Maximum number of downloads running at the same time across all modules
+    pub max_parallel_downloads: Option<usize>,
+    /// Bandwidth shared by all downloads
+    pub rate_limit: Option<RateLimit>,
+    /// Address the HTTP API listens on like `127.0.0.1:8080`, disabled if unset
+    pub api: Option<String>,
+    /// Token API requests have to send as `Authorization: Bearer <token>`
+    pub api_token: Option<String>,
+}
+
+/// Top level global config
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GlobalConfig {
+    /// Hoard Configuration
+    pub hoard: HoardConfig,
+    /// Configuration for the `YouTube` Module
+    pub youtube: Option<crate::youtube::YouTubeConfig>,
+    /// Configuration for the `SoundCloud` Module
+    pub soundcloud: Option<crate::soundcloud::SoundCloudConfig>,
+    /// Custom instances of `yt-dlp`
+    pub yt_dlp: Option<Vec<YtDlpConfig>>,
+    /// Download options for every module
+    pub defaults: Option<DownloadOptions>,
+    /// Named download options, pulled in with `preset = "<name>"`
+    pub presets: Option<HashMap<String, DownloadOptions>>,
+}
+
+impl GlobalConfig {
+    /// Put every module into dry-run mode
+    pub fn set_dry_run(&mut self) {
+        if let Some(youtube) = &mut self.youtube {
+            youtube.dry_run = Some(true);
+        }
+        if let Some(soundcloud) = &mut self.soundcloud {
+            soundcloud.dry_run = Some(true);
+        }
+        for module in self.yt_dlp.iter_mut().flatten() {
+            module.dry_run = Some(true);
+        }
+    }
+
+    /// Layer the defaults and presets below the options of every module and item.
+    ///
+    /// The precedence is defaults, module preset, module, item preset and item.
+    pub fn apply_presets(&mut self) {
+        let presets = self.presets.clone().unwrap_or_default();
+        let defaults = presets::resolve(&presets, &self.defaults.clone().unwrap_or_default());
+        let resolve =
+            |options: &DownloadOptions| defaults.merge(&presets::resolve(&presets, options));
+
+        if let Some(youtube) = &mut self.youtube {
+            youtube.options = resolve(&youtube.options);
+            presets::resolve_items(&presets, &mut youtube.channels);
+        }
+        if let Some(soundcloud) = &mut self.soundcloud {
+            soundcloud.options = resolve(&soundcloud.options);
+            presets::resolve_items(&presets, &mut soundcloud.artists);
+        }
+        for module in self.yt_dlp.iter_mut().flatten() {
+            module.options = resolve(&module.options);
+            presets::resolve_items(&presets, &mut module.items);
+        }
+    }
+
+    /// Read, parse and validate the config file at `path`.
+    ///
+    /// # Returns
+    ///
+    /// An error message listing every problem found, see [`validate::validate`].
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+        let mut raw: toml::Table = toml::from_str(&content)
+            .map_err(|e| format!("Invalid config {}: {e}", path.display()))?;
+
+        let (origins, mut errors) = include::include(path, &mut raw);
+        let mut located = secrets::resolve(&mut raw);
+        cookies::absolute(&mut raw, path, &origins);
+        let resolved = located.is_empty();
+        located.extend(validate::validate(&raw));
+        // unresolved references would only show up as type errors again
+        let config = match raw.try_into::<Self>() {
+            _ if !resolved => None,
+            Ok(config) => Some(config),
+            Err(e) => {
+                // the error ends with "in `<path>`", report it like the others
+                let message = e.to_string();
+                let message = match message.trim_end().rsplit_once("\nin `") {
+                    Some((message, path)) => {
+                        format!("{}: {message}", path.trim_end_matches('`'))
+                    }
+                    None => message.trim_end().to_string(),
+                };
+                located.push(message);
+                None
+            }
+        };
+        errors.extend(located.into_iter().map(|e| origins.locate(e)));
+
+        match config {
+            Some(mut config) if errors.is_empty() => {
+                config.apply_presets();
+                Ok(config)
+            }
+            _ => Err(secrets::redact(&format!(
+                "Invalid config {}:\n{}",
+                path.display(),
+                errors
+                    .iter()
+                    .map(|e| format!("  {e}"))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            ))),
+        }
+    }
+}
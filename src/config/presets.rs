@@ -0,0 +1,75 @@
+//! Named option presets.
+//!
+//! A module, item or preset pulls in a preset with `preset = "<name>"`.
+//! Options set next to the `preset` key take precedence over the ones of the preset.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::yt_dlp::config::{is_item, DownloadOptions};
+
+/// Expand the preset of `options`, following presets which pull in further presets.
+///
+/// Unknown presets and cycles end the chain, they are reported by the validation.
+pub fn resolve(
+    presets: &HashMap<String, DownloadOptions>,
+    options: &DownloadOptions,
+) -> DownloadOptions {
+    let mut layers = vec![options];
+    let mut seen = HashSet::new();
+    let mut next = options.preset.as_ref();
+    while let Some(name) = next {
+        if !seen.insert(name) {
+            break;
+        }
+        let Some(preset) = presets.get(name) else {
+            break;
+        };
+        layers.push(preset);
+        next = preset.preset.as_ref();
+    }
+
+    layers
+        .into_iter()
+        .rev()
+        .fold(DownloadOptions::default(), |base, layer| base.merge(layer))
+}
+
+/// Expand the presets of all inline table items in `items`, including nested categories.
+///
+/// The options of the preset are added to the item table unless the item sets them itself.
+pub fn resolve_items(
+    presets: &HashMap<String, DownloadOptions>,
+    items: &mut HashMap<String, toml::Value>,
+) {
+    for value in items.values_mut() {
+        resolve_item(presets, value);
+    }
+}
+
+fn resolve_item(presets: &HashMap<String, DownloadOptions>, value: &mut toml::Value) {
+    let toml::Value::Table(table) = value else {
+        return;
+    };
+    if !is_item(table) {
+        for (_, value) in table.iter_mut() {
+            resolve_item(presets, value);
+        }
+        return;
+    }
+
+    let Some(toml::Value::String(name)) = table.get("preset") else {
+        return;
+    };
+    let preset = resolve(
+        presets,
+        &DownloadOptions {
+            preset: Some(name.clone()),
+            ..Default::default()
+        },
+    );
+    if let Ok(toml::Value::Table(preset)) = toml::Value::try_from(preset) {
+        for (key, value) in preset {
+            table.entry(key).or_insert(value);
+        }
+    }
+}
@@ -0,0 +1,221 @@
+//! Environment variable and secret file references in the config.
+//!
+//! Every string may contain `${NAME}`, which is replaced by the environment variable `NAME`
+//! (`$${` is a literal `${`). A table `{ file = "<path>" }` is replaced by the contents of the file.
+//!
+//! The contents of secret files and the variables used in [`SECRET_KEYS`] are remembered
+//! and [`redact`]ed from logs and dumps of the config.
+
+use std::sync::{PoisonError, RwLock};
+
+use super::validate::key;
+
+/// Values shorter than this are not redacted, they would garble every log line
+const MIN_SECRET_LEN: usize = 4;
+
+/// What resolved values are replaced with
+const REDACTED: &str = "[redacted]";
+
+/// Keys whose values are secret, along with everything nested in them
+const SECRET_KEYS: &[&str] = &["cookie", "webhooks", "api_token"];
+
+static SECRETS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+/// Resolve all references in `config`.
+///
+/// # Returns
+///
+/// Every reference which could not be resolved as `path: message`.
+pub fn resolve(config: &mut toml::Table) -> Vec<String> {
+    let mut errors = Vec::new();
+    for (name, value) in config.iter_mut() {
+        let secret = SECRET_KEYS.contains(&name.as_str());
+        resolve_value(&key(name), value, secret, &mut errors);
+    }
+    errors
+}
+
+/// Replace every resolved secret in `text`
+pub fn redact(text: &str) -> String {
+    let secrets = SECRETS.read().unwrap_or_else(PoisonError::into_inner);
+    secrets.iter().fold(text.to_string(), |text, secret| {
+        text.replace(secret, REDACTED)
+    })
+}
+
+fn register(secret: &str) {
+    if secret.len() < MIN_SECRET_LEN {
+        return;
+    }
+    let mut secrets = SECRETS.write().unwrap_or_else(PoisonError::into_inner);
+    if !secrets.iter().any(|s| s == secret) {
+        secrets.push(secret.to_string());
+        // replace longer secrets first, they may contain shorter ones
+        secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+    }
+}
+
+/// Resolve the references in `value`, `secret` if it is nested in one of the [`SECRET_KEYS`]
+fn resolve_value(path: &str, value: &mut toml::Value, secret: bool, errors: &mut Vec<String>) {
+    match value {
+        toml::Value::String(text) => match interpolate(text, secret) {
+            Ok(resolved) => *text = resolved,
+            Err(e) => errors.push(format!("{path}: {e}")),
+        },
+        toml::Value::Array(values) => {
+            for (i, value) in values.iter_mut().enumerate() {
+                resolve_value(&format!("{path}[{i}]"), value, secret, errors);
+            }
+        }
+        toml::Value::Table(table) => {
+            if let Some(file) = file_reference(table) {
+                match interpolate(file, secret).and_then(|file| read_secret(&file)) {
+                    Ok(secret) => *value = toml::Value::String(secret),
+                    Err(e) => errors.push(format!("{path}.file: {e}")),
+                }
+                return;
+            }
+            for (name, value) in table.iter_mut() {
+                let secret = secret || SECRET_KEYS.contains(&name.as_str());
+                resolve_value(&format!("{path}.{}", key(name)), value, secret, errors);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The path of a `{ file = "<path>" }` table.
+///
+/// A category holding a single item called `file` looks the same, but its value is a URL.
+fn file_reference(table: &toml::Table) -> Option<&str> {
+    match table.get("file") {
+        Some(toml::Value::String(file)) if table.len() == 1 && !file.contains("://") => Some(file),
+        _ => None,
+    }
+}
+
+fn read_secret(file: &str) -> Result<String, String> {
+    let content =
+        std::fs::read_to_string(file).map_err(|e| format!("could not read \"{file}\": {e}"))?;
+    let secret = content.trim_end_matches(['\n', '\r']).to_string();
+    register(&secret);
+    Ok(secret)
+}
+
+/// Replace `${NAME}` in `text` by the environment variable `NAME`, redacted if `secret`
+fn interpolate(text: &str, secret: bool) -> Result<String, String> {
+    let mut resolved = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        resolved.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(escaped) = rest.strip_prefix("$${") {
+            resolved.push_str("${");
+            rest = escaped;
+        } else if let Some(reference) = rest.strip_prefix("${") {
+            let end = reference
+                .find('}')
+                .ok_or_else(|| format!("missing `}}` after `${{` in \"{text}\""))?;
+            let name = &reference[..end];
+            let value = std::env::var(name)
+                .map_err(|_| format!("environment variable {name} is not set"))?;
+            if secret {
+                register(&value);
+            }
+            resolved.push_str(&value);
+            rest = &reference[end + 1..];
+        } else {
+            resolved.push('$');
+            rest = &rest[1..];
+        }
+    }
+    resolved.push_str(rest);
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolved(config: &str) -> (toml::Table, Vec<String>) {
+        let mut config: toml::Table = toml::from_str(config).unwrap();
+        let errors = resolve(&mut config);
+        (config, errors)
+    }
+
+    #[test]
+    fn interpolates_environment_variables() {
+        std::env::set_var("HOARD_TEST_DIR", "downloads");
+        assert_eq!(
+            interpolate("/data/${HOARD_TEST_DIR}/x", false).unwrap(),
+            "/data/downloads/x"
+        );
+        assert_eq!(
+            interpolate("cost: $5, literal $${HOARD_TEST_DIR}", false).unwrap(),
+            "cost: $5, literal ${HOARD_TEST_DIR}"
+        );
+        assert_eq!(
+            interpolate("${HOARD_TEST_UNSET}", false).unwrap_err(),
+            "environment variable HOARD_TEST_UNSET is not set"
+        );
+        assert!(interpolate("${HOARD_TEST_DIR", false).is_err());
+    }
+
+    #[test]
+    fn redacts_only_secrets() {
+        std::env::set_var("HOARD_TEST_MODULE", "plainmodule");
+        std::env::set_var("HOARD_TEST_TOKEN", "hooktoken123");
+        let (config, errors) = resolved(
+            r#"
+            [[yt_dlp]]
+            name = "${HOARD_TEST_MODULE}"
+            webhooks = ["https://hooks.example.com/${HOARD_TEST_TOKEN}"]
+            "#,
+        );
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(
+            config["yt_dlp"][0]["webhooks"][0].as_str(),
+            Some("https://hooks.example.com/hooktoken123")
+        );
+        assert_eq!(
+            redact("plainmodule sent to hooktoken123"),
+            "plainmodule sent to [redacted]"
+        );
+    }
+
+    #[test]
+    fn reads_secret_files() {
+        let file = std::env::temp_dir().join(format!("hoard-secret-{}", std::process::id()));
+        std::fs::write(&file, "filesecret456\n").unwrap();
+        let (config, errors) = resolved(&format!(
+            "[hoard]\napi_token = {{ file = \"{}\" }}\n",
+            file.display()
+        ));
+        std::fs::remove_file(&file).unwrap();
+
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(config["hoard"]["api_token"].as_str(), Some("filesecret456"));
+        assert_eq!(redact("token filesecret456"), "token [redacted]");
+
+        let (_, errors) = resolved("[hoard]\napi_token = { file = \"/does/not/exist\" }\n");
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("hoard.api_token.file: could not read"));
+    }
+
+    #[test]
+    fn category_with_item_called_file() {
+        let (config, errors) = resolved(
+            r#"
+            [[yt_dlp]]
+            [yt_dlp.items.Category]
+            file = "https://example.com/file"
+            "#,
+        );
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(
+            config["yt_dlp"][0]["items"]["Category"]["file"].as_str(),
+            Some("https://example.com/file")
+        );
+    }
+}
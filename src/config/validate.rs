@@ -0,0 +1,605 @@
+//! Strict validation of the config file.
+//!
+//! Serde can not reject unknown keys next to `#[serde(flatten)]`, so the raw TOML is checked
+//! against the keys the config structs know about. Every problem is reported with its TOML path.
+
+use std::{collections::HashMap, fmt::Display, net::SocketAddr, path::Path};
+
+use serde::Serialize;
+
+use crate::{
+    bandwidth::RateLimit,
+    config::{GlobalConfig, HoardConfig},
+    queue,
+    schedule::{parse_schedule, TimeWindow},
+    soundcloud::SoundCloudConfig,
+    youtube::YouTubeConfig,
+    yt_dlp::config::{is_item, DownloadOptions, YtDlpConfig},
+};
+
+/// Check the raw config for problems.
+///
+/// # Returns
+///
+/// Every problem found as `path: message`.
+pub fn validate(config: &toml::Table) -> Vec<String> {
+    let mut validator = Validator::default();
+    validator.config(config);
+    validator.errors
+}
+
+#[derive(Default)]
+struct Validator {
+    errors: Vec<String>,
+    /// Module directories and the module which uses them
+    dirs: HashMap<String, String>,
+    /// The `[presets]` table
+    presets: toml::Table,
+}
+
+impl Validator {
+    fn error(&mut self, path: &str, message: impl Display) {
+        self.errors.push(format!("{path}: {message}"));
+    }
+
+    fn config(&mut self, config: &toml::Table) {
+        self.unknown_keys("", config, &keys_of::<GlobalConfig>());
+
+        if let Some(toml::Value::Table(hoard)) = config.get("hoard") {
+            self.unknown_keys("hoard", hoard, &keys_of::<HoardConfig>());
+            match hoard.get("max_parallel_downloads") {
+                Some(toml::Value::Integer(max)) if *max <= 0 => {
+                    self.error("hoard.max_parallel_downloads", "must be greater than 0");
+                }
+                _ => {}
+            }
+            self.rate_limit("hoard", hoard);
+            if let Some(toml::Value::String(api)) = hoard.get("api") {
+                if api.parse::<SocketAddr>().is_err() {
+                    self.error(
+                        "hoard.api",
+                        format!("invalid address \"{api}\", expected one like \"127.0.0.1:8080\""),
+                    );
+                }
+            }
+        }
+
+        if let Some(toml::Value::Table(presets)) = config.get("presets") {
+            self.presets = presets.clone();
+            for (name, preset) in presets {
+                let path = format!("presets.{}", key(name));
+                match preset {
+                    toml::Value::Table(preset) => {
+                        self.options(&path, preset);
+                        self.preset_cycle(&path, name);
+                    }
+                    _ => self.error(&path, "expected a table of download options"),
+                }
+            }
+        }
+
+        if let Some(toml::Value::Table(defaults)) = config.get("defaults") {
+            self.options("defaults", defaults);
+        }
+
+        if let Some(toml::Value::Table(youtube)) = config.get("youtube") {
+            self.module("youtube", youtube, &keys_of::<YouTubeConfig>(), "channels");
+            self.module_dir("youtube", "youtube");
+        }
+
+        if let Some(toml::Value::Table(soundcloud)) = config.get("soundcloud") {
+            self.module(
+                "soundcloud",
+                soundcloud,
+                &keys_of::<SoundCloudConfig>(),
+                "artists",
+            );
+            self.module_dir("soundcloud", "soundcloud");
+        }
+
+        if let Some(toml::Value::Array(modules)) = config.get("yt_dlp") {
+            for (i, module) in modules.iter().enumerate() {
+                let toml::Value::Table(module) = module else {
+                    continue;
+                };
+                let path = format!("yt_dlp[{i}]");
+                self.module(&path, module, &keys_of::<YtDlpConfig>(), "items");
+
+                match module.get("name") {
+                    Some(toml::Value::String(name)) => {
+                        self.module_dir(&format!("{path}.name"), name);
+                    }
+                    _ => self.module_dir(&path, "yt_dlp"),
+                }
+            }
+        }
+    }
+
+    /// Check the settings of a module and its items in `items_key`
+    fn module(&mut self, path: &str, module: &toml::Table, known: &[String], items_key: &str) {
+        self.unknown_keys(path, module, known);
+
+        for name in ["interval", "concurrency"] {
+            match module.get(name) {
+                Some(toml::Value::Integer(value)) if *value <= 0 => {
+                    self.error(&format!("{path}.{name}"), "must be greater than 0");
+                }
+                _ => {}
+            }
+        }
+        match (module.get("interval"), module.get("schedule")) {
+            (None, None) => self.error(path, "either `interval` or `schedule` is required"),
+            (Some(_), Some(_)) => self.error(
+                &format!("{path}.schedule"),
+                "can not be combined with `interval`",
+            ),
+            _ => self.schedule(path, module),
+        }
+        self.cookie(path, module);
+        self.max_attempts(path, module);
+        self.active_hours(path, module);
+        self.rate_limit(path, module);
+        self.preset(path, module);
+
+        if let Some(toml::Value::Table(items)) = module.get(items_key) {
+            let path = format!("{path}.{items_key}");
+            if items.contains_key(queue::CATEGORY) {
+                self.error(
+                    &format!("{path}.{}", key(queue::CATEGORY)),
+                    "this name is reserved for URLs added with `hoard add`",
+                );
+            }
+            self.items(&path, items);
+        }
+    }
+
+    /// Check items and categories, see [`YtDlpConfig::items`]
+    fn items(&mut self, path: &str, items: &toml::Table) {
+        let mut item_keys = keys_of::<DownloadOptions>();
+        item_keys.push("url".to_string());
+
+        for (name, value) in items {
+            let path = format!("{path}.{}", key(name));
+            match value {
+                toml::Value::Table(item) if is_item(item) => {
+                    self.unknown_keys(&path, item, &item_keys);
+                    self.urls(&format!("{path}.url"), &item["url"]);
+                    self.cookie(&path, item);
+                    self.max_attempts(&path, item);
+                    self.active_hours(&path, item);
+                    self.schedule(&path, item);
+                    self.preset(&path, item);
+                }
+                toml::Value::Table(category) => self.items(&path, category),
+                urls => self.urls(&path, urls),
+            }
+        }
+    }
+
+    /// Check a table of download options like `[defaults]` or a preset
+    fn options(&mut self, path: &str, options: &toml::Table) {
+        self.unknown_keys(path, options, &keys_of::<DownloadOptions>());
+        if options.contains_key("schedule") {
+            self.error(
+                &format!("{path}.schedule"),
+                "only modules and items can have a schedule",
+            );
+        }
+        self.cookie(path, options);
+        self.max_attempts(path, options);
+        self.active_hours(path, options);
+        self.preset(path, options);
+    }
+
+    /// Check that the `preset` in `table` exists
+    fn preset(&mut self, path: &str, table: &toml::Table) {
+        let path = format!("{path}.preset");
+        match table.get("preset") {
+            None => {}
+            Some(toml::Value::String(name)) if self.presets.contains_key(name) => {}
+            Some(toml::Value::String(name)) => {
+                let known: Vec<String> = self.presets.keys().cloned().collect();
+                match similar_key(name, &known) {
+                    Some(similar) => self.error(
+                        &path,
+                        format!("unknown preset \"{name}\", did you mean \"{similar}\"?"),
+                    ),
+                    None => self.error(&path, format!("unknown preset \"{name}\"")),
+                }
+            }
+            Some(_) => self.error(&path, "expected the name of a preset"),
+        }
+    }
+
+    /// Report if the preset `name` pulls in itself
+    fn preset_cycle(&mut self, path: &str, name: &str) {
+        let mut chain = vec![name];
+        let mut next = name;
+        while let Some(toml::Value::String(preset)) = self
+            .presets
+            .get(next)
+            .and_then(|preset| preset.get("preset"))
+        {
+            if preset == name {
+                chain.push(preset);
+                let message = format!("preset pulls in itself ({})", chain.join(" -> "));
+                self.error(&format!("{path}.preset"), message);
+                return;
+            }
+            if chain.contains(&preset.as_str()) {
+                // a cycle further down the chain, reported at its own presets
+                return;
+            }
+            chain.push(preset);
+            next = preset;
+        }
+    }
+
+    fn urls(&mut self, path: &str, urls: &toml::Value) {
+        match urls {
+            toml::Value::String(url) => self.url(path, url),
+            toml::Value::Array(urls) => {
+                for (i, url) in urls.iter().enumerate() {
+                    match url {
+                        toml::Value::String(url) => self.url(&format!("{path}[{i}]"), url),
+                        _ => self.error(&format!("{path}[{i}]"), "expected a URL"),
+                    }
+                }
+            }
+            _ => self.error(path, "expected a URL, an array of URLs or a table"),
+        }
+    }
+
+    fn url(&mut self, path: &str, url: &str) {
+        if let Err(e) = reqwest::Url::parse(url) {
+            self.error(path, format!("\"{url}\" is not a valid URL ({e})"));
+        }
+    }
+
+    fn cookie(&mut self, path: &str, table: &toml::Table) {
+        if let Some(toml::Value::String(cookie)) = table.get("cookie") {
+            if !Path::new(cookie).is_file() {
+                self.error(
+                    &format!("{path}.cookie"),
+                    format!("cookie file \"{cookie}\" does not exist"),
+                );
+            }
+        }
+    }
+
+    fn max_attempts(&mut self, path: &str, table: &toml::Table) {
+        if let Some(toml::Value::Integer(attempts)) = table.get("max_attempts") {
+            if *attempts <= 0 {
+                self.error(&format!("{path}.max_attempts"), "must be greater than 0");
+            }
+        }
+    }
+
+    fn active_hours(&mut self, path: &str, table: &toml::Table) {
+        if let Some(toml::Value::String(window)) = table.get("active_hours") {
+            if let Err(e) = window.parse::<TimeWindow>() {
+                self.error(&format!("{path}.active_hours"), e);
+            }
+        }
+    }
+
+    fn schedule(&mut self, path: &str, table: &toml::Table) {
+        if let Some(toml::Value::String(schedule)) = table.get("schedule") {
+            if let Err(e) = parse_schedule(schedule) {
+                self.error(&format!("{path}.schedule"), e);
+            }
+        }
+    }
+
+    fn rate_limit(&mut self, path: &str, table: &toml::Table) {
+        let Some(limit) = table.get("rate_limit") else {
+            return;
+        };
+        let path = format!("{path}.rate_limit");
+        match limit.clone().try_into::<RateLimit>() {
+            Ok(limit) => {
+                if let Err(e) = limit.validate() {
+                    self.error(&path, e);
+                }
+            }
+            Err(_) => self.error(
+                &path,
+                "expected a rate like \"5M\" or a table of rates by time of day",
+            ),
+        }
+    }
+
+    /// Remember the directory of a module and report if another module uses it already
+    fn module_dir(&mut self, path: &str, dir: &str) {
+        let module = path.trim_end_matches(".name");
+        match self.dirs.get(dir) {
+            Some(other) => {
+                let message = format!("module directory \"{dir}\" is already used by {other}");
+                self.error(path, message);
+            }
+            None => {
+                self.dirs.insert(dir.to_string(), module.to_string());
+            }
+        }
+    }
+
+    fn unknown_keys(&mut self, path: &str, table: &toml::Table, known: &[String]) {
+        for name in table.keys() {
+            if known.contains(name) {
+                continue;
+            }
+            let path = if path.is_empty() {
+                key(name)
+            } else {
+                format!("{path}.{}", key(name))
+            };
+            match similar_key(name, known) {
+                Some(similar) => {
+                    self.error(&path, format!("unknown key, did you mean \"{similar}\"?"));
+                }
+                None => self.error(&path, "unknown key"),
+            }
+        }
+    }
+}
+
+/// Keys of a config struct, taken from its serialized default
+fn keys_of<T: Default + Serialize>() -> Vec<String> {
+    match serde_json::to_value(T::default()) {
+        Ok(serde_json::Value::Object(map)) => map.into_iter().map(|(key, _)| key).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Quote a key for a TOML path if it is not a bare key
+pub(super) fn key(name: &str) -> String {
+    let bare = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if bare {
+        name.to_string()
+    } else {
+        format!("\"{name}\"")
+    }
+}
+
+/// The known key closest to a misspelled `name`, if any is close enough
+fn similar_key<'a>(name: &str, known: &'a [String]) -> Option<&'a str> {
+    known
+        .iter()
+        .map(|key| (edit_distance(name, key), key))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, key)| key.as_str())
+}
+
+/// Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(config: &str) -> Vec<String> {
+        validate(&toml::from_str(config).unwrap())
+    }
+
+    #[test]
+    fn valid_config() {
+        let errors = errors(
+            r#"
+            [hoard]
+            data_dir = "data"
+            rate_limit = { "08:00-18:00" = "2M", default = "unlimited" }
+            [presets.audio]
+            audio_only = true
+            [[yt_dlp]]
+            name = "Music"
+            schedule = "0 */6 * * *"
+            active_hours = "22:00-06:00"
+            preset = "audio"
+            [yt_dlp.items]
+            Plain = "https://example.com/plain"
+            Inline = { url = ["https://example.com/a"], limit = 5 }
+            "#,
+        );
+        assert!(errors.is_empty(), "{errors:?}");
+    }
+
+    #[test]
+    fn unknown_keys_with_suggestions() {
+        let errors = errors(
+            r#"
+            extra = 1
+            [hoard]
+            data_dir = "data"
+            dbb = "x"
+            [youtube]
+            interval = 60
+            write_comment = true
+            [youtube.channels]
+            Inline = { url = "https://example.com", limt = 3 }
+            "#,
+        );
+        assert!(errors.contains(&"extra: unknown key".to_string()));
+        assert!(errors.contains(&"hoard.dbb: unknown key, did you mean \"db\"?".to_string()));
+        assert!(errors.contains(
+            &"youtube.write_comment: unknown key, did you mean \"write_comments\"?".to_string()
+        ));
+        assert!(errors.contains(
+            &"youtube.channels.Inline.limt: unknown key, did you mean \"limit\"?".to_string()
+        ));
+    }
+
+    #[test]
+    fn duplicate_module_dirs() {
+        let errors = errors(
+            r#"
+            [youtube]
+            interval = 60
+            channels = {}
+            [[yt_dlp]]
+            name = "youtube"
+            interval = 60
+            items = {}
+            [[yt_dlp]]
+            interval = 60
+            items = {}
+            [[yt_dlp]]
+            interval = 60
+            items = {}
+            "#,
+        );
+        assert_eq!(
+            errors,
+            [
+                "yt_dlp[0].name: module directory \"youtube\" is already used by youtube",
+                "yt_dlp[2]: module directory \"yt_dlp\" is already used by yt_dlp[1]",
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_values() {
+        let errors = errors(
+            r#"
+            [hoard]
+            data_dir = "data"
+            max_parallel_downloads = 0
+            rate_limit = "fast"
+            [[yt_dlp]]
+            name = "a"
+            interval = 0
+            concurrency = 0
+            active_hours = "night"
+            rate_limit = { "25:00-06:00" = "1M" }
+            cookie = "/does/not/exist.txt"
+            max_attempts = 0
+            preset = "archiv"
+            [yt_dlp.items]
+            Bad = "not a url"
+            Many = ["https://example.com", 3]
+            [presets.archive]
+            limit = 5
+            "#,
+        );
+        for expected in [
+            "hoard.max_parallel_downloads: must be greater than 0",
+            "hoard.rate_limit: invalid rate \"fast\", expected bytes per second like \"5M\" or \"unlimited\"",
+            "yt_dlp[0].interval: must be greater than 0",
+            "yt_dlp[0].concurrency: must be greater than 0",
+            "yt_dlp[0].active_hours: invalid time window \"night\", expected \"HH:MM-HH:MM\"",
+            "yt_dlp[0].rate_limit: invalid time window \"25:00-06:00\", expected \"HH:MM-HH:MM\"",
+            "yt_dlp[0].cookie: cookie file \"/does/not/exist.txt\" does not exist",
+            "yt_dlp[0].max_attempts: must be greater than 0",
+            "yt_dlp[0].preset: unknown preset \"archiv\", did you mean \"archive\"?",
+            "yt_dlp[0].items.Many[1]: expected a URL",
+        ] {
+            assert!(errors.contains(&expected.to_string()), "{expected} in {errors:?}");
+        }
+        assert!(errors
+            .iter()
+            .any(|e| e.starts_with("yt_dlp[0].items.Bad: \"not a url\" is not a valid URL")));
+    }
+
+    #[test]
+    fn interval_or_schedule() {
+        let errors = errors(
+            r#"
+            [[yt_dlp]]
+            name = "none"
+            items = {}
+            [[yt_dlp]]
+            name = "both"
+            interval = 60
+            schedule = "0 * * * *"
+            items = {}
+            [[yt_dlp]]
+            name = "bad"
+            schedule = "every hour"
+            items = {}
+            "#,
+        );
+        assert_eq!(errors.len(), 3, "{errors:?}");
+        assert_eq!(
+            errors[0],
+            "yt_dlp[0]: either `interval` or `schedule` is required"
+        );
+        assert_eq!(
+            errors[1],
+            "yt_dlp[1].schedule: can not be combined with `interval`"
+        );
+        assert!(errors[2].starts_with("yt_dlp[2].schedule: invalid cron expression"));
+    }
+
+    #[test]
+    fn reserved_category() {
+        let errors = errors(
+            r#"
+            [[yt_dlp]]
+            name = "a"
+            interval = 60
+            [yt_dlp.items."(added)"]
+            Channel = "https://example.com/channel"
+            "#,
+        );
+        assert_eq!(
+            errors,
+            [r#"yt_dlp[0].items."(added)": this name is reserved for URLs added with `hoard add`"#]
+        );
+    }
+
+    #[test]
+    fn item_schedules() {
+        let errors = errors(
+            r#"
+            [defaults]
+            schedule = "0 * * * *"
+            [[yt_dlp]]
+            name = "a"
+            interval = 60
+            [yt_dlp.items]
+            Daily = { url = "https://example.com/daily", schedule = "0 3 * * *" }
+            Broken = { url = "https://example.com/broken", schedule = "sometimes" }
+            "#,
+        );
+        assert_eq!(errors.len(), 2, "{errors:?}");
+        assert_eq!(
+            errors[0],
+            "defaults.schedule: only modules and items can have a schedule"
+        );
+        assert!(errors[1].starts_with("yt_dlp[0].items.Broken.schedule: invalid cron expression"));
+    }
+
+    #[test]
+    fn preset_cycles() {
+        let errors = errors(
+            r#"
+            [presets.a]
+            preset = "b"
+            [presets.b]
+            preset = "a"
+            "#,
+        );
+        assert_eq!(
+            errors,
+            [
+                "presets.a.preset: preset pulls in itself (a -> b -> a)",
+                "presets.b.preset: preset pulls in itself (b -> a -> b)",
+            ]
+        );
+    }
+}
@@ -0,0 +1,345 @@
+//! Versioned schema migrations.
+//!
+//! The schema version is tracked in `PRAGMA user_version`. Every migration
+//! in [`MIGRATIONS`] bumps the version by one, so new migrations must only ever be appended.
+
+use rusqlite::{Connection, Transaction};
+
+use crate::yt_dlp::entry::media_id_from_url;
+
+/// A single step of the database schema
+struct Migration {
+    /// Short description for logging
+    description: &'static str,
+    /// Apply the migration
+    up: fn(&Transaction) -> rusqlite::Result<()>,
+}
+
+/// All migrations in order. The index + 1 is the schema version after applying it.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        description: "initial schema",
+        up: initial_schema,
+    },
+    Migration {
+        description: "media IDs for urls",
+        up: media_ids,
+    },
+    Migration {
+        description: "download records",
+        up: downloads,
+    },
+    Migration {
+        description: "failed download attempts",
+        up: download_attempts,
+    },
+    Migration {
+        description: "error kinds for download attempts",
+        up: attempt_error_kinds,
+    },
+    Migration {
+        description: "download queue",
+        up: download_queue,
+    },
+];
+
+/// The schema version this binary expects
+pub const fn latest_version() -> usize {
+    MIGRATIONS.len()
+}
+
+/// Read the current schema version of the database
+pub fn schema_version(conn: &Connection) -> rusqlite::Result<usize> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+/// Bring the database schema up to date.
+///
+/// All pending migrations run in a single transaction, so a failing migration leaves the database untouched.
+///
+/// # Returns
+///
+/// An error message if a migration fails or the database was created by a newer version of hoard.
+pub fn migrate(conn: &mut Connection) -> Result<(), String> {
+    let current = schema_version(conn).map_err(|e| e.to_string())?;
+    let latest = latest_version();
+
+    if current > latest {
+        return Err(format!(
+            "Database schema version {current} is newer than the supported version {latest}. Please upgrade hoard."
+        ));
+    }
+
+    if current == latest {
+        return Ok(());
+    }
+
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(current) {
+        let version = version + 1;
+        log::info!(
+            "Migrating database to version {version}: {}",
+            migration.description
+        );
+        (migration.up)(&tx).map_err(|e| {
+            format!(
+                "Migration to version {version} ({}) failed: {e}",
+                migration.description
+            )
+        })?;
+    }
+    tx.pragma_update(None, "user_version", latest)
+        .map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())
+}
+
+/// Tables as created by the first versions of hoard, before `user_version` was tracked
+fn initial_schema(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS urls (
+            id INTEGER PRIMARY KEY,
+            url TEXT NOT NULL,
+            timestamp TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS item_log (
+            id INTEGER PRIMARY KEY,
+            module TEXT NOT NULL,
+            name TEXT NOT NULL,
+            url TEXT NOT NULL,
+            timestamp TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Add the `extractor` and `media_id` columns to `urls`
+/// and backfill them for every row where the identity can be derived from the URL.
+fn media_ids(tx: &Transaction) -> rusqlite::Result<()> {
+    // databases from before versioned migrations may already have the columns
+    if tx.prepare("SELECT media_id FROM urls LIMIT 0").is_err() {
+        tx.execute("ALTER TABLE urls ADD COLUMN extractor TEXT", [])?;
+        tx.execute("ALTER TABLE urls ADD COLUMN media_id TEXT", [])?;
+    }
+
+    let rows: Vec<(i64, String)> = tx
+        .prepare("SELECT id, url FROM urls WHERE media_id IS NULL")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    for (id, url) in rows {
+        if let Some((extractor, media_id)) = media_id_from_url(&url) {
+            tx.execute(
+                "UPDATE urls SET extractor = ?, media_id = ? WHERE id = ?",
+                rusqlite::params![extractor, media_id, id],
+            )?;
+        }
+    }
+
+    tx.execute(
+        "CREATE INDEX IF NOT EXISTS urls_media_id ON urls (extractor, media_id)",
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Detailed records of completed downloads
+fn downloads(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS downloads (
+            id INTEGER PRIMARY KEY,
+            module TEXT NOT NULL,
+            item TEXT NOT NULL,
+            category TEXT,
+            extractor TEXT NOT NULL,
+            media_id TEXT NOT NULL,
+            url TEXT NOT NULL,
+            title TEXT NOT NULL,
+            upload_date TEXT,
+            files TEXT NOT NULL,
+            size INTEGER NOT NULL,
+            duration REAL,
+            timestamp TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    tx.execute(
+        "CREATE INDEX IF NOT EXISTS downloads_item ON downloads (module, item)",
+        [],
+    )?;
+    tx.execute(
+        "CREATE INDEX IF NOT EXISTS downloads_media_id ON downloads (extractor, media_id)",
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Failed download attempts for retrying with backoff
+fn download_attempts(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS download_attempts (
+            id INTEGER PRIMARY KEY,
+            extractor TEXT NOT NULL,
+            media_id TEXT NOT NULL,
+            url TEXT NOT NULL,
+            title TEXT NOT NULL,
+            module TEXT NOT NULL,
+            item TEXT NOT NULL,
+            attempts INTEGER NOT NULL,
+            last_error TEXT NOT NULL,
+            next_attempt TEXT NOT NULL,
+            failed INTEGER NOT NULL DEFAULT 0,
+            timestamp TEXT NOT NULL,
+            UNIQUE (extractor, media_id)
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Category of the last error, see [`crate::yt_dlp::error::DownloadError::kind`]
+fn attempt_error_kinds(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute(
+        "ALTER TABLE download_attempts ADD COLUMN error_kind TEXT NOT NULL DEFAULT 'other'",
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Entries found by the modules which are waiting for their download, see [`crate::db::QueueState`]
+fn download_queue(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS queue (
+            id INTEGER PRIMARY KEY,
+            module TEXT NOT NULL,
+            item TEXT NOT NULL,
+            category TEXT,
+            dir TEXT NOT NULL,
+            item_url TEXT NOT NULL,
+            extractor TEXT NOT NULL,
+            media_id TEXT NOT NULL,
+            entry TEXT NOT NULL,
+            state TEXT NOT NULL DEFAULT 'pending',
+            error TEXT,
+            added TEXT NOT NULL,
+            updated TEXT NOT NULL,
+            UNIQUE (module, extractor, media_id)
+        )",
+        [],
+    )?;
+
+    tx.execute(
+        "CREATE INDEX IF NOT EXISTS queue_state ON queue (module, state)",
+        [],
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Schema and some rows of a database created before versioned migrations
+    const BASELINE_FIXTURE: &str = "
+        CREATE TABLE urls (
+            id INTEGER PRIMARY KEY,
+            url TEXT NOT NULL,
+            timestamp TEXT NOT NULL
+        );
+        CREATE TABLE item_log (
+            id INTEGER PRIMARY KEY,
+            module TEXT NOT NULL,
+            name TEXT NOT NULL,
+            url TEXT NOT NULL,
+            timestamp TEXT NOT NULL
+        );
+        INSERT INTO urls (url, timestamp) VALUES
+            ('https://www.youtube.com/watch?v=dQw4w9WgXcQ', '2024-03-01T12:00:00+01:00'),
+            ('https://youtu.be/jNQXAC9IVRw', '2024-03-02T12:00:00+01:00'),
+            ('https://soundcloud.com/artist/track', '2024-03-03T12:00:00+01:00');
+        INSERT INTO item_log (module, name, url, timestamp) VALUES
+            ('youtube', 'Channel', 'https://www.youtube.com/@Channel', '2024-03-02T12:00:00+01:00');
+    ";
+
+    fn baseline() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(BASELINE_FIXTURE).unwrap();
+        conn
+    }
+
+    fn media_id_of(conn: &Connection, url: &str) -> Option<(String, String)> {
+        conn.query_row(
+            "SELECT extractor, media_id FROM urls WHERE url = ?",
+            [url],
+            |row| Ok((row.get::<_, Option<String>>(0)?, row.get(1)?)),
+        )
+        .map(|(extractor, media_id)| extractor.zip(media_id))
+        .unwrap()
+    }
+
+    #[test]
+    fn fresh_database() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), latest_version());
+    }
+
+    #[test]
+    fn upgrade_from_baseline() {
+        let mut conn = baseline();
+        assert_eq!(schema_version(&conn).unwrap(), 0);
+
+        migrate(&mut conn).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), latest_version());
+
+        let urls: i64 = conn
+            .query_row("SELECT COUNT(*) FROM urls", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(urls, 3);
+        let items: i64 = conn
+            .query_row("SELECT COUNT(*) FROM item_log", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(items, 1);
+
+        assert_eq!(
+            media_id_of(&conn, "https://www.youtube.com/watch?v=dQw4w9WgXcQ"),
+            Some(("youtube".to_string(), "dQw4w9WgXcQ".to_string()))
+        );
+        assert_eq!(
+            media_id_of(&conn, "https://youtu.be/jNQXAC9IVRw"),
+            Some(("youtube".to_string(), "jNQXAC9IVRw".to_string()))
+        );
+        assert_eq!(
+            media_id_of(&conn, "https://soundcloud.com/artist/track"),
+            None
+        );
+    }
+
+    #[test]
+    fn migrate_is_idempotent() {
+        let mut conn = baseline();
+        migrate(&mut conn).unwrap();
+        migrate(&mut conn).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), latest_version());
+    }
+
+    #[test]
+    fn refuses_newer_database() {
+        let mut conn = baseline();
+        conn.pragma_update(None, "user_version", latest_version() + 1)
+            .unwrap();
+        assert!(migrate(&mut conn).is_err());
+        assert_eq!(schema_version(&conn).unwrap(), latest_version() + 1);
+    }
+}
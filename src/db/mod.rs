@@ -0,0 +1,872 @@
+use jobdispatcher::{JobDispatcher, JobOrder};
+use rusqlite::{Connection, OptionalExtension};
+use std::{
+    collections::HashSet,
+    path::PathBuf,
+    sync::{mpsc::Receiver, Arc, Mutex, PoisonError},
+};
+
+use crate::yt_dlp::{entry::Entry, error::DownloadError};
+
+pub mod migrations;
+
+pub struct DatabaseBackend {
+    pub file: String,
+    pub conn: Connection,
+    /// Dropped once the backend runs, see [`DatabaseBackend::run`]
+    pub dispatcher: Option<Arc<JobDispatcher<Query, Out>>>,
+    pub recv: Receiver<JobOrder<Query, Out>>,
+}
+
+impl DatabaseBackend {
+    /// Open the database at `file` and bring its schema up to date.
+    ///
+    /// # Returns
+    ///
+    /// An error message if the database can not be opened or migrated.
+    pub fn new(file: &str) -> Result<Self, String> {
+        let (dispatcher, recv) = jobdispatcher::JobDispatcher::<Query, Out>::new();
+        let mut conn = Connection::open(file).map_err(|e| e.to_string())?;
+
+        migrations::migrate(&mut conn)?;
+
+        let dispatcher = Some(Arc::new(dispatcher));
+        Ok(Self {
+            file: file.to_string(),
+            conn,
+            dispatcher,
+            recv,
+        })
+    }
+
+    pub fn take_db(&self) -> Database {
+        Database::new(
+            self.dispatcher
+                .clone()
+                .expect("the dispatcher is only dropped by run"),
+        )
+    }
+
+    /// Answer queries until every [`Database`] handle is dropped.
+    ///
+    /// Queries which are already queued are still answered, so nothing is lost on shutdown.
+    pub fn run(mut self) {
+        // our own handle would keep the queue open forever
+        self.dispatcher = None;
+        while let Ok(job) = self.recv.recv() {
+            match job.param {
+                Query::InsertUrl(ref entry) => {
+                    let timestamp = chrono::Local::now().to_rfc3339();
+                    self.conn
+                        .execute(
+                            "INSERT INTO urls (url, timestamp, extractor, media_id) VALUES (?, ?, ?, ?)",
+                            [&entry.url, &timestamp, &entry.extractor, &entry.id],
+                        )
+                        .unwrap();
+                    job.done(Out::Ok);
+                }
+                Query::CheckForUrl(ref entry) => {
+                    // Rows which could not be backfilled only have their URL
+                    let mut stmt = self
+                        .conn
+                        .prepare("SELECT COUNT(*) FROM urls WHERE (extractor = ? AND media_id = ?) OR url = ?")
+                        .unwrap();
+                    let count: i64 = stmt
+                        .query_row([&entry.extractor, &entry.id, &entry.url], |row| row.get(0))
+                        .unwrap();
+                    job.done(Out::Bool(count > 0));
+                }
+                Query::InsertDownload(ref download) => {
+                    let timestamp = chrono::Local::now().to_rfc3339();
+                    let files = serde_json::to_string(&download.files).unwrap();
+                    self.conn
+                        .execute(
+                            "INSERT INTO downloads (module, item, category, extractor, media_id, url, title, upload_date, files, size, duration, timestamp)
+                            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                            rusqlite::params![
+                                download.module,
+                                download.item,
+                                download.category,
+                                download.entry.extractor,
+                                download.entry.id,
+                                download.entry.url,
+                                download.entry.title,
+                                download.entry.upload_date,
+                                files,
+                                download.size,
+                                download.entry.duration,
+                                timestamp
+                            ],
+                        )
+                        .unwrap();
+                    job.done(Out::Ok);
+                }
+                Query::GetAttempt(ref entry) => {
+                    let attempt = self
+                        .conn
+                        .query_row(
+                            "SELECT attempts, error_kind, last_error, next_attempt, failed FROM download_attempts WHERE extractor = ? AND media_id = ?",
+                            [&entry.extractor, &entry.id],
+                            |row| {
+                                Ok((
+                                    row.get::<_, u32>(0)?,
+                                    row.get::<_, String>(1)?,
+                                    row.get::<_, String>(2)?,
+                                    row.get::<_, String>(3)?,
+                                    row.get::<_, bool>(4)?,
+                                ))
+                            },
+                        )
+                        .optional()
+                        .unwrap()
+                        .map(|(attempts, kind, message, next_attempt, failed)| Attempt {
+                            attempts,
+                            last_error: DownloadError::from_kind(&kind, message),
+                            next_attempt: chrono::DateTime::parse_from_rfc3339(&next_attempt)
+                                .map_or_else(|_| chrono::Local::now(), Into::into),
+                            failed,
+                        });
+                    job.done(Out::Attempt(attempt));
+                }
+                Query::RecordAttempt(ref entry, ref module, ref item, ref attempt) => {
+                    let timestamp = chrono::Local::now().to_rfc3339();
+                    self.conn
+                        .execute(
+                            "INSERT INTO download_attempts (extractor, media_id, url, title, module, item, attempts, error_kind, last_error, next_attempt, failed, timestamp)
+                            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                            ON CONFLICT (extractor, media_id) DO UPDATE SET
+                                url = excluded.url, title = excluded.title, module = excluded.module, item = excluded.item,
+                                attempts = excluded.attempts, error_kind = excluded.error_kind, last_error = excluded.last_error,
+                                next_attempt = excluded.next_attempt, failed = excluded.failed, timestamp = excluded.timestamp",
+                            rusqlite::params![
+                                entry.extractor,
+                                entry.id,
+                                entry.url,
+                                entry.title,
+                                module,
+                                item,
+                                attempt.attempts,
+                                attempt.last_error.kind(),
+                                attempt.last_error.message(),
+                                attempt.next_attempt.to_rfc3339(),
+                                attempt.failed,
+                                timestamp
+                            ],
+                        )
+                        .unwrap();
+                    job.done(Out::Ok);
+                }
+                Query::ClearAttempt(ref entry) => {
+                    self.conn
+                        .execute(
+                            "DELETE FROM download_attempts WHERE extractor = ? AND media_id = ?",
+                            [&entry.extractor, &entry.id],
+                        )
+                        .unwrap();
+                    job.done(Out::Ok);
+                }
+                Query::UpdateNewDownloads(ref module, ref name, ref url) => {
+                    let timestamp = chrono::Local::now().to_rfc3339();
+
+                    // Check if the entry exists
+                    let existing_timestamp: Option<String> = self.conn.query_row(
+                        "SELECT timestamp FROM item_log WHERE module = ? AND name = ? AND url = ?",
+                        [module, name, url],
+                        |row| row.get(0)
+                    ).optional().unwrap();
+
+                    if existing_timestamp.is_some() {
+                        // Entry exists, update timestamp
+                        self.conn.execute(
+                            "UPDATE item_log SET timestamp = ? WHERE module = ? AND name = ? AND url = ?",
+                            [&timestamp, module, name, url]
+                        ).unwrap();
+                    } else {
+                        // Entry doesn't exist, insert new row
+                        self.conn.execute(
+                            "INSERT INTO item_log (module, name, url, timestamp) VALUES (?, ?, ?, ?)",
+                            [module, name, url, &timestamp]
+                        ).unwrap();
+                    }
+
+                    job.done(Out::Ok);
+                }
+                Query::ListDownloads(ref module, ref item) => {
+                    let downloads = self
+                        .list_downloads(module.as_deref(), item.as_deref())
+                        .unwrap();
+                    job.done(Out::Downloads(downloads));
+                }
+                Query::Status => {
+                    job.done(Out::Status(self.status().unwrap()));
+                }
+                Query::Forget(ref url, ref identity) => {
+                    let count = self.forget(url, identity.as_ref()).unwrap();
+                    job.done(Out::Count(count));
+                }
+                Query::Enqueue(ref entry) => {
+                    let id = self.enqueue(entry).unwrap();
+                    job.done(Out::Id(id));
+                }
+                Query::Queued(ref module) => {
+                    let queued = self.queued(module).unwrap();
+                    job.done(Out::Queued(queued));
+                }
+                Query::StartQueued(id) => {
+                    let started = self.start_queued(id).unwrap();
+                    job.done(Out::Bool(started));
+                }
+                Query::FinishQueued(id, state, ref error) => {
+                    self.finish_queued(id, state, error.as_deref()).unwrap();
+                    job.done(Out::Ok);
+                }
+                Query::RecoverQueue => {
+                    let count = self.recover_queue().unwrap();
+                    job.done(Out::Count(count));
+                }
+            }
+        }
+    }
+
+    fn list_downloads(
+        &self,
+        module: Option<&str>,
+        item: Option<&str>,
+    ) -> rusqlite::Result<Vec<DownloadRecord>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT module, item, category, extractor, media_id, url, title, upload_date, files, size, duration, timestamp
+            FROM downloads
+            WHERE (?1 IS NULL OR module = ?1 COLLATE NOCASE) AND (?2 IS NULL OR item = ?2)
+            ORDER BY timestamp",
+        )?;
+
+        let rows = stmt.query_map(rusqlite::params![module, item], |row| {
+            let files: String = row.get(8)?;
+            Ok(DownloadRecord {
+                download: Download {
+                    module: row.get(0)?,
+                    item: row.get(1)?,
+                    category: row.get(2)?,
+                    entry: Entry {
+                        extractor: row.get(3)?,
+                        id: row.get(4)?,
+                        url: row.get(5)?,
+                        title: row.get(6)?,
+                        upload_date: row.get(7)?,
+                        duration: row.get(10)?,
+                        live_status: None,
+                        availability: None,
+                    },
+                    files: serde_json::from_str(&files).unwrap_or_default(),
+                    size: row.get(9)?,
+                },
+                timestamp: row.get(11)?,
+            })
+        })?;
+
+        rows.collect()
+    }
+
+    fn status(&self) -> rusqlite::Result<Status> {
+        let count =
+            |sql: &str| -> rusqlite::Result<u64> { self.conn.query_row(sql, [], |row| row.get(0)) };
+
+        let mut stmt = self.conn.prepare(
+            "SELECT module, COUNT(*), COALESCE(SUM(size), 0), MAX(timestamp) FROM downloads GROUP BY module ORDER BY module",
+        )?;
+        let modules = stmt
+            .query_map([], |row| {
+                Ok(ModuleStatus {
+                    module: row.get(0)?,
+                    downloads: row.get(1)?,
+                    size: row.get(2)?,
+                    last_download: row.get(3)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        let mut stmt = self.conn.prepare(
+            "SELECT module, item, title, url, attempts, error_kind, last_error FROM download_attempts WHERE failed = 1 ORDER BY timestamp DESC",
+        )?;
+        let failed = stmt
+            .query_map([], |row| {
+                Ok(FailedEntry {
+                    module: row.get(0)?,
+                    item: row.get(1)?,
+                    title: row.get(2)?,
+                    url: row.get(3)?,
+                    attempts: row.get(4)?,
+                    error: DownloadError::from_kind(&row.get::<_, String>(5)?, row.get(6)?),
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(Status {
+            urls: count("SELECT COUNT(*) FROM urls")?,
+            downloads: count("SELECT COUNT(*) FROM downloads")?,
+            size: count("SELECT COALESCE(SUM(size), 0) FROM downloads")?,
+            retrying: count("SELECT COUNT(*) FROM download_attempts WHERE failed = 0")?,
+            queued: count("SELECT COUNT(*) FROM queue WHERE state IN ('pending', 'running')")?,
+            modules,
+            failed,
+        })
+    }
+
+    /// Remove every trace of a download, returns the number of removed URLs
+    fn forget(&self, url: &str, identity: Option<&(String, String)>) -> rusqlite::Result<usize> {
+        let (extractor, media_id) = identity.cloned().unzip();
+        let params = rusqlite::params![url, extractor, media_id];
+
+        let count = self.conn.execute(
+            "DELETE FROM urls WHERE url = ?1 OR (extractor = ?2 AND media_id = ?3)",
+            params,
+        )?;
+        self.conn.execute(
+            "DELETE FROM downloads WHERE url = ?1 OR (extractor = ?2 AND media_id = ?3)",
+            params,
+        )?;
+        self.conn.execute(
+            "DELETE FROM download_attempts WHERE url = ?1 OR (extractor = ?2 AND media_id = ?3)",
+            params,
+        )?;
+
+        Ok(count)
+    }
+
+    /// Add an entry to the queue of its module, returns its queue ID.
+    ///
+    /// Entries which are queued already keep their place. Finished ones are queued again
+    /// since they were not found in the archive, at the end with a new ID and the new item,
+    /// so modules which handled them before see them as new work.
+    fn enqueue(&self, queued: &QueuedEntry) -> rusqlite::Result<i64> {
+        let timestamp = chrono::Local::now().to_rfc3339();
+        let entry = serde_json::to_string(&queued.entry).unwrap();
+        let params = rusqlite::params![
+            queued.module,
+            queued.item,
+            queued.category,
+            queued.dir.to_string_lossy(),
+            queued.item_url,
+            queued.entry.extractor,
+            queued.entry.id,
+            entry,
+            timestamp
+        ];
+        self.conn.execute(
+            "UPDATE queue SET id = (SELECT MAX(id) FROM queue) + 1, item = ?2, category = ?3, dir = ?4,
+                item_url = ?5, entry = ?8, state = 'pending', error = NULL, added = ?9, updated = ?9
+            WHERE module = ?1 AND extractor = ?6 AND media_id = ?7 AND state IN ('done', 'failed')",
+            params,
+        )?;
+        self.conn.execute(
+            "INSERT INTO queue (module, item, category, dir, item_url, extractor, media_id, entry, state, added, updated)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, 'pending', ?9, ?9)
+            ON CONFLICT (module, extractor, media_id) DO UPDATE SET updated = excluded.updated",
+            params,
+        )?;
+        self.conn.query_row(
+            "SELECT id FROM queue WHERE module = ? AND extractor = ? AND media_id = ?",
+            [&queued.module, &queued.entry.extractor, &queued.entry.id],
+            |row| row.get(0),
+        )
+    }
+
+    /// Mark a pending entry as running, `false` if it is not pending anymore
+    fn start_queued(&self, id: i64) -> rusqlite::Result<bool> {
+        let started = self.conn.execute(
+            "UPDATE queue SET state = 'running', updated = ? WHERE id = ? AND state = 'pending'",
+            rusqlite::params![chrono::Local::now().to_rfc3339(), id],
+        )?;
+        Ok(started > 0)
+    }
+
+    fn finish_queued(
+        &self,
+        id: i64,
+        state: QueueState,
+        error: Option<&str>,
+    ) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "UPDATE queue SET state = ?, error = ?, updated = ? WHERE id = ?",
+            rusqlite::params![state.as_str(), error, chrono::Local::now().to_rfc3339(), id],
+        )?;
+        Ok(())
+    }
+
+    /// Queue downloads again which were running when hoard stopped without cleaning up
+    fn recover_queue(&self) -> rusqlite::Result<usize> {
+        self.conn.execute(
+            "UPDATE queue SET state = 'pending' WHERE state = 'running'",
+            [],
+        )
+    }
+
+    /// Pending entries of a module in the order they were found
+    fn queued(&self, module: &str) -> rusqlite::Result<Vec<QueuedEntry>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, module, item, category, dir, item_url, entry FROM queue
+            WHERE module = ? AND state = 'pending' ORDER BY id",
+        )?;
+
+        let rows = stmt.query_map([module], |row| {
+            let entry: String = row.get(6)?;
+            let Ok(entry) = serde_json::from_str(&entry) else {
+                return Ok(None);
+            };
+            Ok(Some(QueuedEntry {
+                id: row.get(0)?,
+                module: row.get(1)?,
+                item: row.get(2)?,
+                category: row.get(3)?,
+                dir: PathBuf::from(row.get::<_, String>(4)?),
+                item_url: row.get(5)?,
+                entry,
+            }))
+        })?;
+
+        // rows with an unreadable entry are left for the next listing to replace
+        Ok(rows
+            .collect::<rusqlite::Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect())
+    }
+}
+
+/// A completed download with the files it produced
+#[derive(Debug, Clone)]
+pub struct Download {
+    /// Module which downloaded the entry
+    pub module: String,
+    /// Item the entry belongs to
+    pub item: String,
+    /// Category of the item
+    pub category: Option<String>,
+    /// The downloaded entry
+    pub entry: Entry,
+    /// Final paths of the downloaded files
+    pub files: Vec<PathBuf>,
+    /// Combined size of all files in bytes
+    pub size: u64,
+}
+
+/// Failed download attempts of an entry
+#[derive(Debug, Clone)]
+pub struct Attempt {
+    /// Number of failed attempts so far
+    pub attempts: u32,
+    /// Error of the last attempt
+    pub last_error: DownloadError,
+    /// Earliest time for the next attempt
+    pub next_attempt: chrono::DateTime<chrono::Local>,
+    /// Whether hoard gave up on this entry
+    pub failed: bool,
+}
+
+/// State of an entry in the download queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueState {
+    /// Waiting for a download worker
+    Pending,
+    /// Claimed by a download worker, recovered as pending after a crash
+    Running,
+    /// Downloaded
+    Done,
+    /// Given up on, see [`Attempt::failed`]
+    Failed,
+}
+
+impl QueueState {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Running => "running",
+            Self::Done => "done",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+/// An entry in the download queue of a module
+#[derive(Debug, Clone)]
+pub struct QueuedEntry {
+    /// Queue ID, `0` until the entry was queued
+    pub id: i64,
+    pub module: String,
+    /// Name of the item the entry was found under
+    pub item: String,
+    /// Category of the item
+    pub category: Option<String>,
+    /// Download directory relative to the module directory
+    pub dir: PathBuf,
+    /// Source URL of the item the entry was listed from
+    pub item_url: String,
+    pub entry: Entry,
+}
+
+/// A completed download as stored in the database
+#[derive(Debug, Clone)]
+pub struct DownloadRecord {
+    pub download: Download,
+    /// When the download finished
+    pub timestamp: String,
+}
+
+/// Overview of the archive
+#[derive(Debug, Clone)]
+pub struct Status {
+    /// Number of URLs marked as downloaded
+    pub urls: u64,
+    /// Number of recorded downloads
+    pub downloads: u64,
+    /// Combined size of all recorded downloads in bytes
+    pub size: u64,
+    /// Entries waiting for a retry
+    pub retrying: u64,
+    /// Entries waiting for their download
+    pub queued: u64,
+    /// Downloads per module
+    pub modules: Vec<ModuleStatus>,
+    /// Entries hoard gave up on
+    pub failed: Vec<FailedEntry>,
+}
+
+/// Downloads of a single module
+#[derive(Debug, Clone)]
+pub struct ModuleStatus {
+    pub module: String,
+    pub downloads: u64,
+    pub size: u64,
+    pub last_download: Option<String>,
+}
+
+/// An entry hoard gave up on
+#[derive(Debug, Clone)]
+pub struct FailedEntry {
+    pub module: String,
+    pub item: String,
+    pub title: String,
+    pub url: String,
+    pub attempts: u32,
+    pub error: DownloadError,
+}
+
+pub enum Query {
+    InsertUrl(Entry),
+    CheckForUrl(Entry),
+    InsertDownload(Download),
+    GetAttempt(Entry),
+    RecordAttempt(Entry, String, String, Attempt),
+    ClearAttempt(Entry),
+    UpdateNewDownloads(String, String, String),
+    ListDownloads(Option<String>, Option<String>),
+    Status,
+    Forget(String, Option<(String, String)>),
+    Enqueue(QueuedEntry),
+    Queued(String),
+    StartQueued(i64),
+    FinishQueued(i64, QueueState, Option<String>),
+    RecoverQueue,
+}
+
+pub enum Out {
+    Ok,
+    Bool(bool),
+    Attempt(Option<Attempt>),
+    Count(usize),
+    Downloads(Vec<DownloadRecord>),
+    Status(Status),
+    Id(i64),
+    Queued(Vec<QueuedEntry>),
+}
+
+/// Extractor and media ID of entries which are downloading right now
+type InFlight = Arc<Mutex<HashSet<(String, String)>>>;
+
+#[derive(Clone)]
+pub struct Database {
+    conn: Arc<JobDispatcher<Query, Out>>,
+    in_flight: InFlight,
+}
+
+/// An entry claimed for downloading, released when dropped
+pub struct DownloadClaim {
+    in_flight: InFlight,
+    identity: (String, String),
+}
+
+impl Drop for DownloadClaim {
+    fn drop(&mut self) {
+        self.in_flight
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.identity);
+    }
+}
+
+impl Database {
+    pub fn new(conn: Arc<JobDispatcher<Query, Out>>) -> Self {
+        Self {
+            conn,
+            in_flight: Arc::default(),
+        }
+    }
+
+    /// Claim an entry for downloading, so no other module or worker downloads it at the same time.
+    ///
+    /// # Returns
+    /// `None` if the entry is downloading already
+    pub fn claim(&self, entry: &Entry) -> Option<DownloadClaim> {
+        let identity = (entry.extractor.clone(), entry.id.clone());
+        let mut in_flight = self
+            .in_flight
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        in_flight.insert(identity.clone()).then(|| DownloadClaim {
+            in_flight: self.in_flight.clone(),
+            identity,
+        })
+    }
+
+    /// Insert an entry into the database as already downloaded
+    pub fn insert_url(&self, entry: &Entry) {
+        self.conn.send(Query::InsertUrl(entry.clone()));
+    }
+
+    /// Check if an entry is already in the database
+    ///
+    /// Entries are identified by their extractor and media ID, so the same video
+    /// under a different URL (e.g. `youtu.be/X` and `/shorts/X`) counts as downloaded.
+    /// # Return
+    /// Returns `true` if already present, `false` otherwise
+    ///
+    /// # Example
+    /// You could use this function like that:
+    ///
+    /// ```ignore
+    /// if !db.check_for_url(&entry) {
+    ///     // do download
+    /// }
+    /// ```
+    pub fn check_for_url(&self, entry: &Entry) -> bool {
+        match self.conn.send(Query::CheckForUrl(entry.clone())) {
+            Out::Bool(b) => b,
+            _ => false,
+        }
+    }
+
+    /// Save a detailed record of a completed download
+    pub fn insert_download(&self, download: &Download) {
+        self.conn.send(Query::InsertDownload(download.clone()));
+    }
+
+    /// Get the failed download attempts of an entry
+    pub fn get_attempt(&self, entry: &Entry) -> Option<Attempt> {
+        match self.conn.send(Query::GetAttempt(entry.clone())) {
+            Out::Attempt(attempt) => attempt,
+            _ => None,
+        }
+    }
+
+    /// Save the failed download attempts of an entry found by `module` under `item`
+    pub fn record_attempt(&self, entry: &Entry, module: &str, item: &str, attempt: &Attempt) {
+        self.conn.send(Query::RecordAttempt(
+            entry.clone(),
+            module.to_string(),
+            item.to_string(),
+            attempt.clone(),
+        ));
+    }
+
+    /// Forget all failed download attempts of an entry
+    pub fn clear_attempt(&self, entry: &Entry) {
+        self.conn.send(Query::ClearAttempt(entry.clone()));
+    }
+
+    /// Keep a record on when download happen.
+    /// This takes a `module`, `name` and `url` and saves a timestamp to the db.
+    pub fn update_new_downloads(&self, module: &str, name: &str, url: &str) {
+        self.conn.send(Query::UpdateNewDownloads(
+            module.to_string(),
+            name.to_string(),
+            url.to_string(),
+        ));
+    }
+
+    /// List completed downloads, optionally only of a `module` and `item`
+    pub fn list_downloads(&self, module: Option<&str>, item: Option<&str>) -> Vec<DownloadRecord> {
+        match self.conn.send(Query::ListDownloads(
+            module.map(ToString::to_string),
+            item.map(ToString::to_string),
+        )) {
+            Out::Downloads(downloads) => downloads,
+            _ => Vec::new(),
+        }
+    }
+
+    /// Get an overview of the archive
+    pub fn status(&self) -> Option<Status> {
+        match self.conn.send(Query::Status) {
+            Out::Status(status) => Some(status),
+            _ => None,
+        }
+    }
+
+    /// Add an entry to the download queue of its module
+    ///
+    /// # Returns
+    /// The queue ID of the entry
+    pub fn enqueue(&self, entry: &QueuedEntry) -> Option<i64> {
+        match self.conn.send(Query::Enqueue(entry.clone())) {
+            Out::Id(id) => Some(id),
+            _ => None,
+        }
+    }
+
+    /// Pending entries in the download queue of `module`, oldest first
+    pub fn queued(&self, module: &str) -> Vec<QueuedEntry> {
+        match self.conn.send(Query::Queued(module.to_string())) {
+            Out::Queued(queued) => queued,
+            _ => Vec::new(),
+        }
+    }
+
+    /// Mark a queued entry as running
+    ///
+    /// # Returns
+    /// `false` if the entry is not pending anymore
+    pub fn start_queued(&self, id: i64) -> bool {
+        match self.conn.send(Query::StartQueued(id)) {
+            Out::Bool(started) => started,
+            _ => false,
+        }
+    }
+
+    /// Set the state of a queued entry after a download, with the error if it failed
+    pub fn finish_queued(&self, id: i64, state: QueueState, error: Option<String>) {
+        self.conn.send(Query::FinishQueued(id, state, error));
+    }
+
+    /// Queue downloads again which were interrupted by a crash.
+    ///
+    /// Only call this before any module runs, their running downloads would be queued twice.
+    pub fn recover_queue(&self) {
+        if let Out::Count(recovered @ 1..) = self.conn.send(Query::RecoverQueue) {
+            log::info!("Recovered {recovered} interrupted downloads");
+        }
+    }
+
+    /// Forget a download so it will be downloaded again.
+    ///
+    /// Matches either the exact `url` or the `identity` (extractor and media ID).
+    ///
+    /// # Returns
+    /// The number of URLs removed
+    pub fn forget(&self, url: &str, identity: Option<(String, String)>) -> usize {
+        match self.conn.send(Query::Forget(url.to_string(), identity)) {
+            Out::Count(count) => count,
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backend() -> DatabaseBackend {
+        DatabaseBackend::new(":memory:").unwrap()
+    }
+
+    fn queued(id: &str) -> QueuedEntry {
+        QueuedEntry {
+            id: 0,
+            module: "Videos".to_string(),
+            item: "Channel".to_string(),
+            category: None,
+            dir: PathBuf::from("Channel"),
+            item_url: "https://www.youtube.com/@Channel".to_string(),
+            entry: Entry {
+                id: id.to_string(),
+                extractor: "youtube".to_string(),
+                title: format!("Video {id}"),
+                url: format!("https://www.youtube.com/watch?v={id}"),
+                upload_date: None,
+                duration: None,
+                live_status: None,
+                availability: None,
+            },
+        }
+    }
+
+    fn ids(backend: &DatabaseBackend) -> Vec<i64> {
+        backend
+            .queued("Videos")
+            .unwrap()
+            .iter()
+            .map(|queued| queued.id)
+            .collect()
+    }
+
+    #[test]
+    fn enqueue() {
+        let backend = backend();
+        let first = backend.enqueue(&queued("first")).unwrap();
+        let second = backend.enqueue(&queued("second")).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(ids(&backend), [first, second]);
+        assert!(backend.queued("Music").unwrap().is_empty());
+
+        let entry = &backend.queued("Videos").unwrap()[0];
+        assert_eq!(entry.item, "Channel");
+        assert_eq!(entry.dir, PathBuf::from("Channel"));
+        assert_eq!(entry.entry, queued("first").entry);
+
+        // queued again by the next listing, keeps its place
+        assert_eq!(backend.enqueue(&queued("first")).unwrap(), first);
+        assert_eq!(ids(&backend), [first, second]);
+    }
+
+    #[test]
+    fn enqueue_finished() {
+        let backend = backend();
+        let done = backend.enqueue(&queued("done")).unwrap();
+        let failed = backend.enqueue(&queued("failed")).unwrap();
+        let running = backend.enqueue(&queued("running")).unwrap();
+        for id in [done, failed, running] {
+            assert!(backend.start_queued(id).unwrap());
+        }
+        backend.finish_queued(done, QueueState::Done, None).unwrap();
+        backend
+            .finish_queued(failed, QueueState::Failed, Some("gone"))
+            .unwrap();
+        assert!(ids(&backend).is_empty());
+
+        // finished entries are pending again at the end of the queue, running ones are left alone
+        let mut moved = queued("done");
+        moved.item = "Playlist".to_string();
+        let done_again = backend.enqueue(&moved).unwrap();
+        let failed_again = backend.enqueue(&queued("failed")).unwrap();
+        assert_eq!(backend.enqueue(&queued("running")).unwrap(), running);
+        assert!(done_again > running && failed_again > done_again);
+        assert_eq!(ids(&backend), [done_again, failed_again]);
+        assert_eq!(backend.queued("Videos").unwrap()[0].item, "Playlist");
+        assert!(!backend.start_queued(running).unwrap());
+    }
+
+    #[test]
+    fn recover_queue() {
+        let backend = backend();
+        let running = backend.enqueue(&queued("running")).unwrap();
+        let pending = backend.enqueue(&queued("pending")).unwrap();
+        let done = backend.enqueue(&queued("done")).unwrap();
+        assert!(backend.start_queued(running).unwrap());
+        assert!(backend.start_queued(done).unwrap());
+        backend.finish_queued(done, QueueState::Done, None).unwrap();
+        assert_eq!(ids(&backend), [pending]);
+
+        assert_eq!(backend.recover_queue().unwrap(), 1);
+        assert_eq!(ids(&backend), [running, pending]);
+        assert_eq!(backend.recover_queue().unwrap(), 0);
+    }
+}
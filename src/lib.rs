@@ -1,8 +1,21 @@
-use std::path::PathBuf;
+use std::{collections::HashMap, path::PathBuf};
 
+use config::GlobalConfig;
+use db::Database;
+use yt_dlp::config::YtDlpConfig;
+
+pub mod api;
+pub mod bandwidth;
+pub mod cli;
 pub mod config;
 pub mod db;
+pub mod queue;
+pub mod reload;
+pub mod schedule;
+pub mod shutdown;
+pub mod slots;
 pub mod soundcloud;
+pub mod supervisor;
 pub mod youtube;
 pub mod yt_dlp;
 
@@ -16,9 +29,126 @@ pub fn ensure_dir_exists(dir_path: &PathBuf) {
 /// Generic module implementation
 ///
 /// Each module gets it's own thread to work for itself.
-pub trait Module: Send {
+pub trait Module: Send + Sync {
     /// friendly name for module
     fn name(&self) -> String;
+    /// check all items once, returns `false` if any fetch or download failed
+    fn run_cycle(&self) -> bool;
+    /// check the item with this name or path once like [`Module::run_cycle`],
+    /// returns `None` if the module has no such item
+    fn run_item(&self, name: &str) -> Option<bool>;
     /// module main loop
     fn run(&self);
+    /// finish the current download and return from [`Module::run`]
+    fn stop(&self);
+    /// apply a changed config while running, returns `false` if the module has to be restarted
+    fn reconfigure(&self, _config: &ModuleConfig) -> bool {
+        false
+    }
+}
+
+/// Config of a single module type
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModuleConfig {
+    YouTube(youtube::YouTubeConfig),
+    SoundCloud(soundcloud::SoundCloudConfig),
+    YtDlp(YtDlpConfig),
+}
+
+/// Everything needed to build a module.
+///
+/// Two modules with the same key are the same module, possibly with a changed config.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModuleSpec {
+    /// Unique key of the module
+    pub key: String,
+    /// Download directory of the module
+    pub root_dir: PathBuf,
+    pub config: ModuleConfig,
+}
+
+impl ModuleConfig {
+    /// The generic `yt-dlp` config with the defaults of the module type
+    pub fn yt_dlp_config(&self) -> YtDlpConfig {
+        match self {
+            Self::YouTube(config) => config.yt_dlp_config(),
+            Self::SoundCloud(config) => config.yt_dlp_config(),
+            Self::YtDlp(config) => config.clone(),
+        }
+    }
+}
+
+impl ModuleSpec {
+    pub fn build(&self, db: &Database) -> Box<dyn Module> {
+        let root_dir = self.root_dir.clone();
+        match &self.config {
+            ModuleConfig::YouTube(config) => Box::new(youtube::YouTubeModule::new(
+                config.clone(),
+                db.clone(),
+                root_dir,
+            )),
+            ModuleConfig::SoundCloud(config) => Box::new(soundcloud::SoundCloudModule::new(
+                config.clone(),
+                db.clone(),
+                root_dir,
+            )),
+            ModuleConfig::YtDlp(config) => Box::new(yt_dlp::YtDlpModule::new(
+                config.clone(),
+                db.clone(),
+                root_dir,
+            )),
+        }
+    }
+}
+
+/// All modules enabled in `config`
+pub fn module_specs(config: &GlobalConfig) -> Vec<ModuleSpec> {
+    let mut specs = vec![];
+
+    if let Some(yt_config) = &config.youtube {
+        specs.push(ModuleSpec {
+            key: "youtube".to_string(),
+            root_dir: config.hoard.data_dir.join("youtube"),
+            config: ModuleConfig::YouTube(yt_config.clone()),
+        });
+    }
+
+    if let Some(sc_config) = &config.soundcloud {
+        specs.push(ModuleSpec {
+            key: "soundcloud".to_string(),
+            root_dir: config.hoard.data_dir.join("soundcloud"),
+            config: ModuleConfig::SoundCloud(sc_config.clone()),
+        });
+    }
+
+    let mut seen = HashMap::new();
+    for yt_dlp_mod in config.yt_dlp.iter().flatten() {
+        let mod_name = yt_dlp_mod
+            .name
+            .clone()
+            .unwrap_or_else(|| "yt_dlp".to_string());
+        // names are not required to be unique
+        let count = seen.entry(mod_name.clone()).or_insert(0);
+        *count += 1;
+        let key = if *count == 1 {
+            format!("yt_dlp.{mod_name}")
+        } else {
+            format!("yt_dlp.{mod_name}#{count}")
+        };
+        specs.push(ModuleSpec {
+            key,
+            root_dir: config.hoard.data_dir.join(mod_name),
+            config: ModuleConfig::YtDlp(yt_dlp_mod.clone()),
+        });
+    }
+
+    specs
+}
+
+/// Create all modules enabled in `config`
+pub fn build_modules(config: &GlobalConfig, db: &Database) -> Vec<Box<dyn Module>> {
+    module_specs(config)
+        .iter()
+        .map(|spec| spec.build(db))
+        .collect()
 }
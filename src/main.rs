@@ -1,10 +1,12 @@
-use hoard::config::GlobalConfig;
-use hoard::{ensure_dir_exists, Module};
+use std::{io::Write, process::ExitCode};
+
+use clap::Parser;
+use hoard::{cli::Args, config::secrets::redact};
 
 // todo : migrate to async code?
 // todo : better log options
 
-fn main() {
+fn main() -> ExitCode {
     #[cfg(debug_assertions)]
     {
         std::env::set_var("RUST_LOG", "trace");
@@ -17,60 +19,20 @@ fn main() {
             std::env::set_var("RUST_LOG", "warn");
         }
     }
-    env_logger::init();
-
-    log::info!("Starting hoard");
-
-    let db = hoard::db::DatabaseBackend::new("data/download.db");
-    let config: GlobalConfig =
-        toml::from_str(&std::fs::read_to_string("config.toml").unwrap()).unwrap();
-    ensure_dir_exists(&config.hoard.data_dir);
-
-    let mut modules: Vec<Box<dyn Module>> = vec![];
-
-    if let Some(yt_config) = config.youtube {
-        modules.push(Box::new(hoard::youtube::YouTubeModule::new(
-            yt_config,
-            db.take_db(),
-            config.hoard.data_dir.join("youtube"),
-        )));
-    }
-
-    if let Some(sc_config) = config.soundcloud {
-        modules.push(Box::new(hoard::soundcloud::SoundCloudModule::new(
-            sc_config,
-            db.take_db(),
-            config.hoard.data_dir.join("soundcloud"),
-        )));
-    }
-
-    for yt_dlp_mod in config.yt_dlp.unwrap_or_default() {
-        let mod_name = yt_dlp_mod
-            .name
-            .clone()
-            .unwrap_or_else(|| "yt_dlp".to_string());
-        modules.push(Box::new(hoard::yt_dlp::YtDlpModule::new(
-            yt_dlp_mod,
-            db.take_db(),
-            config.hoard.data_dir.join(mod_name),
-        )));
-    }
-
-    let _db_thread = std::thread::spawn(move || {
-        db.run();
-    });
-
-    let threads: Vec<_> = modules
-        .into_iter()
-        .map(|x| {
-            std::thread::spawn(move || {
-                x.run();
-            })
+    // same format as the default, without secrets from the config
+    env_logger::Builder::from_default_env()
+        .format(|buf, record| {
+            let style = buf.default_level_style(record.level());
+            writeln!(
+                buf,
+                "[{} {style}{:<5}{style:#} {}] {}",
+                buf.timestamp(),
+                record.level(),
+                record.target(),
+                redact(&record.args().to_string())
+            )
         })
-        .collect();
+        .init();
 
-    for t in threads {
-        // todo : fix dying threads
-        t.join().unwrap();
-    }
+    Args::parse().run()
 }
@@ -0,0 +1,128 @@
+//! URLs added to the download queue of a module by hand, with `hoard add` or the HTTP API.
+//!
+//! Added entries are downloaded with the options of the module under the reserved [`CATEGORY`],
+//! a sleeping module picks them up within [`POLL_INTERVAL`] or right away when added in the same process.
+
+use std::{
+    path::{Component, Path},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    config::GlobalConfig,
+    db::{Database, QueuedEntry},
+    module_specs, shutdown,
+    yt_dlp::{entry::Entry, resolve_all},
+};
+
+/// How often a sleeping module looks for entries added by another process
+pub const POLL_INTERVAL: Duration = Duration::from_secs(30);
+/// Directory added entries are downloaded to unless another one is given
+pub const DEFAULT_DIR: &str = "added";
+/// Category of added entries, reserved so they never mix with the items of the config
+pub const CATEGORY: &str = "(added)";
+
+/// Counts the URLs added in this process, so sleeping modules notice them
+static ADDED: AtomicU64 = AtomicU64::new(0);
+
+/// Entries found behind an added URL
+#[derive(Debug, Default)]
+pub struct Added {
+    /// Name of the module the entries were queued for
+    pub module: String,
+    /// Entries put into the queue
+    pub queued: Vec<Entry>,
+    /// Entries skipped because they are downloaded already
+    pub downloaded: Vec<Entry>,
+}
+
+/// Number of URLs added in this process so far
+pub fn added() -> u64 {
+    ADDED.load(Ordering::SeqCst)
+}
+
+/// Queue all entries behind `url` for download by `module` into `dir`.
+///
+/// `dir` is relative to the directory of the module. A playlist queues all of its entries,
+/// entries downloaded already are skipped. Nothing is queued in a dry run.
+pub fn add(
+    config: &GlobalConfig,
+    db: &Database,
+    url: &str,
+    module: &str,
+    dir: &str,
+    dry_run: bool,
+) -> Result<Added, String> {
+    let module = module_specs(config)
+        .into_iter()
+        .map(|spec| {
+            spec.config
+                .yt_dlp_config()
+                .name
+                .unwrap_or_else(|| "yt-dlp".to_string())
+        })
+        .find(|name| name.eq_ignore_ascii_case(module))
+        .ok_or_else(|| format!("No module called \"{module}\""))?;
+
+    let path = check_dir(dir)?;
+    let entries = resolve_all(url).map_err(|e| format!("Could not resolve {url}: {e}"))?;
+    let mut added = Added {
+        module,
+        ..Added::default()
+    };
+    for entry in entries {
+        if db.check_for_url(&entry) {
+            added.downloaded.push(entry);
+            continue;
+        }
+
+        if !dry_run {
+            // asked for by hand, so earlier failures get another chance
+            db.clear_attempt(&entry);
+            db.enqueue(&QueuedEntry {
+                id: 0,
+                module: added.module.clone(),
+                item: dir.to_string(),
+                category: Some(CATEGORY.to_string()),
+                dir: path.to_path_buf(),
+                item_url: url.to_string(),
+                entry: entry.clone(),
+            })
+            .ok_or_else(|| format!("Could not queue \"{}\"", entry.title))?;
+        }
+        added.queued.push(entry);
+    }
+
+    if !dry_run && !added.queued.is_empty() {
+        ADDED.fetch_add(1, Ordering::SeqCst);
+        shutdown::wake();
+    }
+    Ok(added)
+}
+
+/// Check that `dir` stays below the module directory
+fn check_dir(dir: &str) -> Result<&Path, String> {
+    let path = Path::new(dir);
+    if dir.is_empty() || !path.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err(format!(
+            "Invalid directory \"{dir}\", expected a path relative to the module directory"
+        ));
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dirs() {
+        for dir in [DEFAULT_DIR, "misc", "music/live", "music/live/"] {
+            assert!(check_dir(dir).is_ok(), "{dir}");
+        }
+        for dir in ["", "/tmp", "../other", "misc/../..", "./misc"] {
+            assert!(check_dir(dir).is_err(), "{dir}");
+        }
+    }
+}
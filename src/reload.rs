@@ -0,0 +1,155 @@
+//! Apply changes of the config file to the running daemon.
+//!
+//! The config file is reloaded when it or one of its included files was modified, or on `SIGHUP`.
+//! Only modules whose config changed are restarted, the others keep running undisturbed.
+
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    api, bandwidth,
+    config::{include, GlobalConfig},
+    db::Database,
+    module_specs, shutdown, slots,
+    supervisor::Supervisor,
+    ModuleSpec,
+};
+
+/// How often the config file is checked for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Runs the modules of the current config and reloads it on changes
+pub struct Reloader {
+    path: PathBuf,
+    config: GlobalConfig,
+    specs: Vec<ModuleSpec>,
+    /// Modification times of the config file and all included files
+    modified: Vec<(PathBuf, Option<SystemTime>)>,
+    db: Database,
+    /// Put every module into dry-run mode, see [`GlobalConfig::set_dry_run`]
+    dry_run: bool,
+}
+
+impl Reloader {
+    pub fn new(path: PathBuf, config: GlobalConfig, db: Database, dry_run: bool) -> Self {
+        Self {
+            modified: modified(&path, &config),
+            specs: module_specs(&config),
+            path,
+            config,
+            db,
+            dry_run,
+        }
+    }
+
+    /// Start all modules and apply config changes until a shutdown is requested
+    pub fn run(mut self, supervisor: &mut Supervisor) {
+        for spec in &self.specs {
+            supervisor.spawn(&spec.key, spec.build(&self.db));
+        }
+
+        loop {
+            shutdown::sleep_unless(POLL_INTERVAL, shutdown::reload_requested);
+            if shutdown::requested() {
+                return;
+            }
+
+            let signalled = shutdown::take_reload();
+            if signalled || modified(&self.path, &self.config) != self.modified {
+                self.reload(supervisor);
+                // the reloaded config may include other files
+                self.modified = modified(&self.path, &self.config);
+            }
+        }
+    }
+
+    /// Load the config file again and restart the modules which changed.
+    ///
+    /// An invalid config is logged and the current one keeps running.
+    fn reload(&mut self, supervisor: &mut Supervisor) {
+        let mut config = match GlobalConfig::load(&self.path) {
+            Ok(config) => config,
+            Err(e) => {
+                log::error!("Keeping the current config: {e}");
+                return;
+            }
+        };
+        if self.dry_run {
+            config.set_dry_run();
+        }
+
+        if config.hoard.db != self.config.hoard.db {
+            log::warn!("Changing the database location requires a restart");
+        }
+        if config.hoard.api != self.config.hoard.api {
+            log::warn!("Changing the API address requires a restart");
+        }
+        if config.hoard.shutdown_grace_period != self.config.hoard.shutdown_grace_period {
+            shutdown::set_grace_period(Duration::from_secs(
+                config
+                    .hoard
+                    .shutdown_grace_period
+                    .unwrap_or(shutdown::DEFAULT_GRACE_PERIOD),
+            ));
+        }
+
+        if config.hoard.max_parallel_downloads != self.config.hoard.max_parallel_downloads {
+            slots::set_limit(config.hoard.max_parallel_downloads);
+        }
+
+        if config.hoard.rate_limit != self.config.hoard.rate_limit {
+            bandwidth::set_global(config.hoard.rate_limit.clone());
+        }
+
+        let specs = module_specs(&config);
+        for old in &self.specs {
+            if !specs.iter().any(|spec| spec.key == old.key) {
+                log::info!("Stopping removed module {}", old.key);
+                supervisor.stop(&old.key);
+            }
+        }
+        for spec in &specs {
+            match self.specs.iter().find(|old| old.key == spec.key) {
+                None => {
+                    log::info!("Starting new module {}", spec.key);
+                    supervisor.spawn(&spec.key, spec.build(&self.db));
+                }
+                Some(old) if old != spec => {
+                    if old.root_dir == spec.root_dir
+                        && supervisor.reconfigure(&spec.key, &spec.config)
+                    {
+                        log::info!("Reconfigured module {}", spec.key);
+                    } else {
+                        log::info!("Restarting changed module {}", spec.key);
+                        supervisor.replace(&spec.key, spec.build(&self.db));
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+
+        api::set_config(&config);
+        self.config = config;
+        self.specs = specs;
+        log::info!("Reloaded {}", self.path.display());
+    }
+}
+
+/// Modification times of the config file and the files its `include` patterns match now,
+/// so added and removed files are noticed as well
+fn modified(path: &Path, config: &GlobalConfig) -> Vec<(PathBuf, Option<SystemTime>)> {
+    let patterns = config.hoard.include.as_deref().unwrap_or_default();
+    let mut files = include::files(path, patterns).unwrap_or_default();
+    files.insert(0, path.to_path_buf());
+    files
+        .into_iter()
+        .map(|file| {
+            let modified = std::fs::metadata(&file)
+                .and_then(|meta| meta.modified())
+                .ok();
+            (file, modified)
+        })
+        .collect()
+}
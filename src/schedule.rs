@@ -0,0 +1,280 @@
+//! When modules check for new entries and when they may download them.
+//!
+//! A module is checked every `interval` minutes or at the times of a cron `schedule`.
+//! Downloads can be limited to `active_hours`, entries found outside of them wait for the next window.
+
+use std::{str::FromStr, time::Duration};
+
+use chrono::{DateTime, Local, NaiveTime, TimeZone};
+
+/// Names of the weekdays by their number in standard cron, where Sunday is 0 or 7
+const WEEKDAYS: [&str; 8] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT", "SUN"];
+
+/// Parse a cron expression.
+///
+/// The usual five fields (minute, hour, day of month, month, day of week) are accepted
+/// as well as a leading seconds field. Weekdays are numbered like in standard cron.
+pub fn parse_schedule(expression: &str) -> Result<cron::Schedule, String> {
+    let invalid = |e: &dyn std::fmt::Display| {
+        format!("invalid cron expression \"{}\": {e}", expression.trim())
+    };
+    let mut fields: Vec<String> = expression.split_whitespace().map(String::from).collect();
+    if fields.len() == 5 {
+        fields.insert(0, "0".to_string());
+    }
+    if let Some(weekdays) = fields.get_mut(5) {
+        *weekdays = weekday_names(weekdays)
+            .ok_or_else(|| invalid(&format!("invalid day of week \"{weekdays}\"")))?;
+    }
+    cron::Schedule::from_str(&fields.join(" ")).map_err(|e| invalid(&e))
+}
+
+/// Replace the weekday numbers of a day of week field by names.
+///
+/// The `cron` crate numbers weekdays from Sunday as 1, so `1-5` would mean Sunday to Thursday.
+/// Names are passed on unchanged, `None` if a number is out of range.
+fn weekday_names(field: &str) -> Option<String> {
+    let parts = field.split(',').map(|part| {
+        if part == "*" || part == "?" || part.chars().any(|c| c.is_ascii_alphabetic()) {
+            return Some(part.to_string());
+        }
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse().ok().filter(|step| *step > 0)?),
+            None => (part, 1),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (0, 6),
+            Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+            // `5/2` counts from 5 to the end of the week
+            None if step > 1 => (range.parse().ok()?, 6),
+            None => (range.parse().ok()?, range.parse().ok()?),
+        };
+        if start > end || end >= WEEKDAYS.len() {
+            return None;
+        }
+        let days: Vec<_> = (start..=end)
+            .step_by(step)
+            .map(|day| WEEKDAYS[day])
+            .collect();
+        Some(days.join(","))
+    });
+    parts
+        .collect::<Option<Vec<_>>>()
+        .map(|parts| parts.join(","))
+}
+
+/// Time from now until `time`, zero if it already passed
+pub fn until(time: DateTime<Local>) -> Duration {
+    (time - Local::now()).to_std().unwrap_or(Duration::ZERO)
+}
+
+/// A daily time window like `01:00-07:00`, which may span midnight like `22:00-06:00`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl TimeWindow {
+    /// Whether `time` lies within the window
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start < self.end {
+            self.start <= time && time < self.end
+        } else {
+            // spans midnight, equal bounds mean the whole day
+            time >= self.start || time < self.end
+        }
+    }
+
+    /// The next time the window opens after `now`
+    pub fn next_start<Tz: TimeZone>(&self, now: DateTime<Tz>) -> DateTime<Tz> {
+        let mut date = now.naive_local().date();
+        if now.naive_local().time() >= self.start {
+            date = date.succ_opt().unwrap_or(date);
+        }
+        let start = date.and_time(self.start);
+        let timezone = now.timezone();
+        timezone
+            .from_local_datetime(&start)
+            .earliest()
+            // the clocks skip the start on this day, the window opens once they moved forward
+            .or_else(|| {
+                timezone
+                    .from_local_datetime(&(start + chrono::Duration::hours(1)))
+                    .earliest()
+            })
+            .unwrap_or_else(|| now + chrono::Duration::hours(1))
+    }
+}
+
+impl FromStr for TimeWindow {
+    type Err = String;
+
+    fn from_str(window: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid time window \"{window}\", expected \"HH:MM-HH:MM\"");
+        let (start, end) = window.split_once('-').ok_or_else(invalid)?;
+        let parse = |time: &str| NaiveTime::parse_from_str(time.trim(), "%H:%M");
+        Ok(Self {
+            start: parse(start).map_err(|_| invalid())?,
+            end: parse(end).map_err(|_| invalid())?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Datelike, NaiveDate, Timelike};
+    use chrono_tz::Europe::Berlin;
+
+    use super::*;
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn berlin(day: (i32, u32, u32), hour: u32, minute: u32) -> DateTime<chrono_tz::Tz> {
+        let date = NaiveDate::from_ymd_opt(day.0, day.1, day.2).unwrap();
+        Berlin
+            .from_local_datetime(&date.and_time(time(hour, minute)))
+            .earliest()
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_windows() {
+        let window: TimeWindow = "01:00-07:30".parse().unwrap();
+        assert_eq!(window.start, time(1, 0));
+        assert_eq!(window.end, time(7, 30));
+        assert_eq!(
+            " 22:00 - 06:00 ".parse::<TimeWindow>().unwrap().end,
+            time(6, 0)
+        );
+
+        for invalid in ["", "01:00", "1-7", "25:00-06:00", "01:00-07:60"] {
+            assert!(invalid.parse::<TimeWindow>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn contains_within_a_day() {
+        let window: TimeWindow = "01:00-07:00".parse().unwrap();
+        assert!(window.contains(time(1, 0)));
+        assert!(window.contains(time(6, 59)));
+        assert!(!window.contains(time(7, 0)));
+        assert!(!window.contains(time(0, 59)));
+        assert!(!window.contains(time(23, 0)));
+    }
+
+    #[test]
+    fn contains_across_midnight() {
+        let window: TimeWindow = "22:00-06:00".parse().unwrap();
+        assert!(window.contains(time(22, 0)));
+        assert!(window.contains(time(23, 59)));
+        assert!(window.contains(time(0, 0)));
+        assert!(window.contains(time(5, 59)));
+        assert!(!window.contains(time(6, 0)));
+        assert!(!window.contains(time(12, 0)));
+
+        // equal bounds are the whole day
+        let day: TimeWindow = "00:00-00:00".parse().unwrap();
+        assert!(day.contains(time(0, 0)));
+        assert!(day.contains(time(13, 37)));
+    }
+
+    #[test]
+    fn next_start_today_or_tomorrow() {
+        let window: TimeWindow = "22:00-06:00".parse().unwrap();
+        let day = (2026, 6, 10);
+        assert_eq!(window.next_start(berlin(day, 12, 0)), berlin(day, 22, 0));
+        // inside the window it opens again tomorrow
+        assert_eq!(
+            window.next_start(berlin(day, 23, 0)),
+            berlin((2026, 6, 11), 22, 0)
+        );
+        assert_eq!(
+            window.next_start(berlin(day, 22, 0)),
+            berlin((2026, 6, 11), 22, 0)
+        );
+    }
+
+    #[test]
+    fn next_start_across_clock_changes() {
+        // 02:30 does not exist on the day the clocks move forward from 02:00 to 03:00
+        let window: TimeWindow = "02:30-05:00".parse().unwrap();
+        let start = window.next_start(berlin((2026, 3, 28), 23, 0));
+        assert_eq!(
+            start.date_naive(),
+            NaiveDate::from_ymd_opt(2026, 3, 29).unwrap()
+        );
+        assert_eq!((start.hour(), start.minute()), (3, 30));
+
+        // 02:30 exists twice when the clocks move back, the window opens the first time
+        let start = window.next_start(berlin((2026, 10, 24), 23, 0));
+        assert_eq!(
+            start.date_naive(),
+            NaiveDate::from_ymd_opt(2026, 10, 25).unwrap()
+        );
+        assert_eq!((start.hour(), start.minute()), (2, 30));
+        assert_eq!(start.offset().to_string(), "CEST");
+    }
+
+    #[test]
+    fn parses_schedules() {
+        let schedule = parse_schedule("0 */6 * * *").unwrap();
+        let after = Local.with_ymd_and_hms(2026, 6, 10, 7, 15, 0).unwrap();
+        let next = schedule.after(&after).next().unwrap();
+        assert_eq!((next.hour(), next.minute(), next.second()), (12, 0, 0));
+
+        // a leading seconds field is accepted as well
+        let schedule = parse_schedule("30 0 12 * * *").unwrap();
+        let next = schedule.after(&after).next().unwrap();
+        assert_eq!((next.hour(), next.minute(), next.second()), (12, 0, 30));
+
+        assert!(parse_schedule("every hour").is_err());
+        assert!(parse_schedule("0 25 * * *").is_err());
+    }
+
+    #[test]
+    fn standard_weekdays() {
+        let weekdays = |expression: &str| {
+            // 2026-06-07 is a Sunday
+            let after = Local.with_ymd_and_hms(2026, 6, 6, 12, 0, 0).unwrap();
+            let schedule = parse_schedule(expression).unwrap();
+            let mut days: Vec<_> = schedule
+                .after(&after)
+                .take(7)
+                .map(|time| time.weekday().num_days_from_sunday())
+                .collect();
+            days.sort_unstable();
+            days.dedup();
+            days
+        };
+        assert_eq!(weekdays("0 3 * * 1-5"), [1, 2, 3, 4, 5]);
+        assert_eq!(weekdays("0 3 * * 0"), [0]);
+        assert_eq!(weekdays("0 3 * * 7"), [0]);
+        assert_eq!(weekdays("0 3 * * 0,6"), [0, 6]);
+        assert_eq!(weekdays("0 3 * * 5-7"), [0, 5, 6]);
+        assert_eq!(weekdays("0 3 * * */2"), [0, 2, 4, 6]);
+        assert_eq!(weekdays("0 3 * * 1-5/2"), [1, 3, 5]);
+        assert_eq!(weekdays("0 3 * * Mon-Fri"), [1, 2, 3, 4, 5]);
+        assert_eq!(weekdays("0 0 3 * * 1"), [1]);
+
+        for invalid in ["0 3 * * 8", "0 3 * * 5-1", "0 3 * * 1/0", "0 3 * * x-1"] {
+            assert!(parse_schedule(invalid).is_err(), "{invalid}");
+        }
+        // the error shows the expression as written
+        assert_eq!(
+            parse_schedule("0 3 * * 9").unwrap_err(),
+            "invalid cron expression \"0 3 * * 9\": invalid day of week \"9\""
+        );
+    }
+
+    #[test]
+    fn until_is_never_negative() {
+        assert_eq!(
+            until(Local::now() - chrono::Duration::minutes(5)),
+            Duration::ZERO
+        );
+        assert!(until(Local::now() + chrono::Duration::minutes(5)) > Duration::from_secs(290));
+    }
+}
@@ -0,0 +1,222 @@
+//! Graceful shutdown on `SIGTERM` and `SIGINT`, config reloads on `SIGHUP`.
+//!
+//! A shutdown stops modules from starting new work and wakes them from their sleep.
+//! Running `yt-dlp` processes get a grace period to finish before they are terminated.
+
+use std::{
+    io::{self, Read},
+    os::unix::process::CommandExt,
+    process::{Child, Command, Output, Stdio},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Condvar, Mutex, PoisonError,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use signal_hook::{
+    consts::{SIGHUP, SIGINT, SIGTERM},
+    iterator::Signals,
+};
+
+/// Default time in seconds running downloads get to finish after a shutdown was requested
+pub const DEFAULT_GRACE_PERIOD: u64 = 60;
+/// How often running child processes are checked
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// Time a child gets to exit after `SIGTERM` before it is killed
+const KILL_AFTER: Duration = Duration::from_secs(10);
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+static RELOAD: AtomicBool = AtomicBool::new(false);
+static GRACE_PERIOD: AtomicU64 = AtomicU64::new(DEFAULT_GRACE_PERIOD);
+static LOCK: Mutex<()> = Mutex::new(());
+static WAKE: Condvar = Condvar::new();
+
+/// Request a shutdown on `SIGTERM` and `SIGINT` and a reload on `SIGHUP`
+pub fn install_handler() -> Result<(), String> {
+    let mut signals = Signals::new([SIGTERM, SIGINT, SIGHUP])
+        .map_err(|e| format!("Could not install signal handler: {e}"))?;
+
+    std::thread::spawn(move || {
+        for signal in signals.forever() {
+            if signal == SIGHUP {
+                log::info!("Received SIGHUP, reloading config");
+                request_reload();
+            } else {
+                log::warn!("Received signal {signal}, shutting down");
+                request();
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Set how long running downloads may continue after a shutdown was requested
+pub fn set_grace_period(grace_period: Duration) {
+    GRACE_PERIOD.store(grace_period.as_secs(), Ordering::Relaxed);
+}
+
+/// How long running downloads may continue after a shutdown was requested
+pub fn grace_period() -> Duration {
+    Duration::from_secs(GRACE_PERIOD.load(Ordering::Relaxed))
+}
+
+/// Stop scheduling new work and wake up all sleeping modules
+pub fn request() {
+    REQUESTED.store(true, Ordering::SeqCst);
+    wake();
+}
+
+/// Whether a shutdown was requested
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Wake up all sleeping threads so they check their conditions again
+pub fn wake() {
+    let _guard = LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    WAKE.notify_all();
+}
+
+/// Request a reload of the config file
+pub fn request_reload() {
+    RELOAD.store(true, Ordering::SeqCst);
+    wake();
+}
+
+/// Whether a reload was requested since the last call
+pub fn take_reload() -> bool {
+    RELOAD.swap(false, Ordering::SeqCst)
+}
+
+/// Whether a reload is waiting to be handled
+pub fn reload_requested() -> bool {
+    RELOAD.load(Ordering::SeqCst)
+}
+
+/// Sleep for `duration` unless a shutdown is requested in the meantime.
+///
+/// # Returns
+///
+/// `false` if the sleep was cut short by a shutdown.
+pub fn sleep(duration: Duration) -> bool {
+    sleep_unless(duration, || false)
+}
+
+/// Sleep for `duration` unless a shutdown is requested or `cancelled` becomes true in the meantime.
+///
+/// `cancelled` is checked whenever sleeping threads are woken with [`wake`].
+///
+/// # Returns
+///
+/// `false` if the sleep was cut short.
+pub fn sleep_unless(duration: Duration, cancelled: impl Fn() -> bool) -> bool {
+    let deadline = Instant::now() + duration;
+    let mut guard = LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    loop {
+        if requested() || cancelled() {
+            return false;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        guard = WAKE
+            .wait_timeout(guard, deadline - now)
+            .unwrap_or_else(PoisonError::into_inner)
+            .0;
+    }
+}
+
+/// Run `command` to completion and collect its output like [`Command::output`].
+///
+/// The child runs in its own process group, so a `SIGINT` from the terminal only reaches hoard.
+/// Once a shutdown is requested the child may keep running for `grace`,
+/// after that its process group is terminated.
+pub fn output(command: &mut Command, grace: Duration) -> io::Result<Output> {
+    output_unless(command, grace, || false).map(|output| output.expect("never interrupted"))
+}
+
+/// Run `command` like [`output`], but terminate it as soon as `interrupt` returns true.
+///
+/// # Returns
+///
+/// `None` if the child was interrupted.
+pub fn output_unless(
+    command: &mut Command,
+    grace: Duration,
+    interrupt: impl Fn() -> bool,
+) -> io::Result<Option<Output>> {
+    let mut child = command
+        .process_group(0)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // read both pipes while waiting, a full pipe would block the child forever
+    let stdout = read_pipe(child.stdout.take());
+    let stderr = read_pipe(child.stderr.take());
+
+    let mut deadline = None;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if requested() {
+            let deadline = *deadline.get_or_insert_with(|| Instant::now() + grace);
+            if Instant::now() >= deadline {
+                log::warn!("Terminating process {}", child.id());
+                terminate(&mut child);
+                break child.wait()?;
+            }
+        } else if interrupt() {
+            terminate(&mut child);
+            child.wait()?;
+            let _ = (stdout.join(), stderr.join());
+            return Ok(None);
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    };
+
+    Ok(Some(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    }))
+}
+
+fn read_pipe(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+/// Send `SIGTERM` to the process group of `child` and kill it if it does not exit in time
+fn terminate(child: &mut Child) {
+    let Ok(pgid) = i32::try_from(child.id()) else {
+        let _ = child.kill();
+        return;
+    };
+
+    // SAFETY: `kill` has no memory safety requirements, the group was created for this child
+    unsafe { libc::kill(-pgid, libc::SIGTERM) };
+
+    let deadline = Instant::now() + KILL_AFTER;
+    while Instant::now() < deadline {
+        if matches!(child.try_wait(), Ok(Some(_))) {
+            return;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+
+    log::warn!("Killing process {pgid}");
+    // SAFETY: see above
+    unsafe { libc::kill(-pgid, libc::SIGKILL) };
+}
@@ -0,0 +1,164 @@
+//! Download slots shared by all modules, limited by `[hoard] max_parallel_downloads`.
+//!
+//! Waiting downloads get a free slot by the `priority` of their module, higher first,
+//! and in the order they asked for one among equal priorities.
+
+use std::{
+    sync::{Condvar, Mutex, PoisonError},
+    time::Duration,
+};
+
+/// How often waiting downloads check whether they should give up
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+static STATE: Mutex<State> = Mutex::new(State {
+    limit: None,
+    running: 0,
+    waiting: Vec::new(),
+    next_ticket: 0,
+});
+static CHANGED: Condvar = Condvar::new();
+
+struct State {
+    /// Maximum number of downloads at once, unlimited if `None`
+    limit: Option<usize>,
+    running: usize,
+    /// Priority and ticket of every waiting download
+    waiting: Vec<(u32, u64)>,
+    next_ticket: u64,
+}
+
+impl State {
+    fn free(&self) -> bool {
+        self.limit.is_none_or(|limit| self.running < limit)
+    }
+
+    /// Whether `ticket` is the next waiting download to get a slot
+    fn is_next(&self, ticket: u64) -> bool {
+        self.waiting
+            .iter()
+            .max_by_key(|(priority, ticket)| (*priority, std::cmp::Reverse(*ticket)))
+            .is_some_and(|(_, next)| *next == ticket)
+    }
+}
+
+/// A running download, frees its slot when dropped
+pub struct Slot(());
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let mut state = STATE.lock().unwrap_or_else(PoisonError::into_inner);
+        state.running -= 1;
+        CHANGED.notify_all();
+    }
+}
+
+/// Set the maximum number of downloads across all modules, `None` for no limit
+pub fn set_limit(limit: Option<usize>) {
+    let mut state = STATE.lock().unwrap_or_else(PoisonError::into_inner);
+    state.limit = limit.map(|limit| limit.max(1));
+    CHANGED.notify_all();
+}
+
+/// Wait for a free download slot.
+///
+/// # Returns
+///
+/// `None` if `cancelled` became true while waiting.
+pub fn acquire(priority: u32, cancelled: impl Fn() -> bool) -> Option<Slot> {
+    let mut state = STATE.lock().unwrap_or_else(PoisonError::into_inner);
+    let ticket = state.next_ticket;
+    state.next_ticket += 1;
+    state.waiting.push((priority, ticket));
+
+    let mut logged = false;
+    loop {
+        if cancelled() {
+            state.waiting.retain(|(_, waiting)| *waiting != ticket);
+            // the next one in line may be able to start now
+            CHANGED.notify_all();
+            return None;
+        }
+        if state.free() && state.is_next(ticket) {
+            state.waiting.retain(|(_, waiting)| *waiting != ticket);
+            state.running += 1;
+            CHANGED.notify_all();
+            return Some(Slot(()));
+        }
+        if !logged {
+            log::debug!("Waiting for a download slot");
+            logged = true;
+        }
+        state = CHANGED
+            .wait_timeout(state, POLL_INTERVAL)
+            .unwrap_or_else(PoisonError::into_inner)
+            .0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    use super::*;
+
+    fn waiting() -> usize {
+        STATE
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .waiting
+            .len()
+    }
+
+    /// Block until `count` downloads wait for a slot
+    fn until_waiting(count: usize) {
+        while waiting() != count {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    // the slots are global, so this is the only test using them
+    #[test]
+    fn priority_then_first_come() {
+        set_limit(Some(1));
+        let held = acquire(0, || false).unwrap();
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let cancel = Arc::new(AtomicBool::new(false));
+        let mut waiters = Vec::new();
+        for (name, priority) in [("low 1", 0), ("cancelled", 10), ("low 2", 0), ("high", 5)] {
+            let order = order.clone();
+            let cancel = cancel.clone();
+            waiters.push(std::thread::spawn(move || {
+                let cancelled = || name == "cancelled" && cancel.load(Ordering::SeqCst);
+                let slot = acquire(priority, cancelled);
+                order.lock().unwrap().push((name, slot.is_some()));
+            }));
+            // one after another, so they get their tickets in this order
+            until_waiting(waiters.len());
+        }
+
+        // the first in line gives up and hands its place on
+        cancel.store(true, Ordering::SeqCst);
+        until_waiting(3);
+        drop(held);
+        for waiter in waiters {
+            waiter.join().unwrap();
+        }
+
+        assert_eq!(
+            *order.lock().unwrap(),
+            [
+                ("cancelled", false),
+                ("high", true),
+                ("low 1", true),
+                ("low 2", true)
+            ]
+        );
+        assert_eq!(STATE.lock().unwrap().running, 0);
+        set_limit(None);
+    }
+}
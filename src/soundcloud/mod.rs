@@ -3,47 +3,67 @@ use std::{collections::HashMap, path::PathBuf};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    yt_dlp::{config::YtDlpConfig, YtDlpModule},
-    Module,
+    bandwidth::RateLimit,
+    yt_dlp::{
+        config::{DownloadOptions, YtDlpConfig},
+        YtDlpModule,
+    },
+    Module, ModuleConfig,
 };
 
 /// Configuration for the `SoundCloud` Module
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct SoundCloudConfig {
     /// Interval in minutes between checks
-    pub interval: u64,
-    /// Amount of items to query
-    pub limit: Option<u64>,
+    pub interval: Option<u64>,
+    /// Number of downloads running at the same time
+    pub concurrency: Option<usize>,
+    /// Modules with a higher priority get a free download slot first
+    pub priority: Option<u32>,
+    /// Bandwidth shared by the downloads of this module
+    pub rate_limit: Option<RateLimit>,
     /// Items to check
     pub artists: HashMap<String, toml::Value>,
-    /// Output Template for yt-dlp
-    pub output_format: Option<String>,
-    /// Download comments
-    pub write_comments: Option<bool>,
-    /// Download description
-    pub write_description: Option<bool>,
-    /// Download cover
-    pub write_cover: Option<bool>,
-    /// Download subtitles
-    pub write_subs: Option<bool>,
-    /// Audio Format
-    pub audio_format: Option<String>,
-    /// Embed thumbnail
-    pub embed_thumbnail: Option<bool>,
-    /// Embed metadata
-    pub embed_metadata: Option<bool>,
-    /// Embed chapters
-    pub embed_chapters: Option<bool>,
-    /// Embed info.json
-    pub embed_info_json: Option<bool>,
-    /// Split by chapter
-    pub split_chapters: Option<bool>,
-    /// Format Selection
-    pub format: Option<String>,
-    /// Cookie File
-    pub cookie: Option<String>,
     /// Webhooks for notifications
     pub webhooks: Option<Vec<String>>,
+    /// Only report what would be downloaded
+    pub dry_run: Option<bool>,
+    /// Download cover, same as `write_thumbnail`
+    pub write_cover: Option<bool>,
+    /// Download options for all items
+    #[serde(flatten)]
+    pub options: DownloadOptions,
+}
+
+impl SoundCloudConfig {
+    /// The generic `yt-dlp` config with the defaults of this module
+    pub fn yt_dlp_config(&self) -> YtDlpConfig {
+        let defaults = DownloadOptions {
+            thumbnail_format: Some("jpg".to_string()),
+            write_description: Some(true),
+            write_info_json: Some(false),
+            write_thumbnail: Some(self.write_cover.unwrap_or(true)),
+            embed_subs: Some(false),
+            embed_thumbnail: Some(true),
+            embed_metadata: Some(true),
+            embed_chapters: Some(true),
+            embed_info_json: Some(true),
+            audio_only: Some(true),
+            ..Default::default()
+        };
+
+        YtDlpConfig {
+            name: Some("soundcloud".to_string()),
+            interval: self.interval,
+            concurrency: self.concurrency,
+            priority: self.priority,
+            rate_limit: self.rate_limit.clone(),
+            items: self.artists.clone(),
+            webhooks: self.webhooks.clone(),
+            dry_run: self.dry_run,
+            options: defaults.merge(&self.options),
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -54,34 +74,7 @@ pub struct SoundCloudModule {
 impl SoundCloudModule {
     pub fn new(config: SoundCloudConfig, db: crate::db::Database, root_dir: PathBuf) -> Self {
         Self {
-            yt_dlp: YtDlpModule::new(
-                YtDlpConfig {
-                    name: Some("soundcloud".to_string()),
-                    interval: config.interval,
-                    limit: config.limit,
-                    items: config.artists,
-                    thumbnail_format: Some("jpg".to_string()),
-                    output_format: config.output_format.clone(),
-                    write_description: Some(config.write_description.unwrap_or(true)),
-                    write_info_json: Some(false),
-                    write_comments: config.write_comments,
-                    write_thumbnail: Some(true),
-                    write_subs: config.write_subs,
-                    audio_format: config.audio_format,
-                    embed_subs: Some(false),
-                    embed_thumbnail: Some(config.embed_thumbnail.unwrap_or(true)),
-                    embed_metadata: Some(config.embed_metadata.unwrap_or(true)),
-                    embed_chapters: Some(config.embed_chapters.unwrap_or(true)),
-                    embed_info_json: Some(config.embed_info_json.unwrap_or(true)),
-                    split_chapters: config.split_chapters,
-                    format: config.format,
-                    cookie: config.cookie,
-                    audio_only: Some(true),
-                    webhooks: config.webhooks,
-                },
-                db,
-                root_dir,
-            ),
+            yt_dlp: YtDlpModule::new(config.yt_dlp_config(), db, root_dir),
         }
     }
 }
@@ -91,7 +84,23 @@ impl Module for SoundCloudModule {
         "SoundCloud".to_string()
     }
 
+    fn run_cycle(&self) -> bool {
+        self.yt_dlp.run_cycle()
+    }
+
+    fn run_item(&self, name: &str) -> Option<bool> {
+        self.yt_dlp.run_item(name)
+    }
+
     fn run(&self) {
         self.yt_dlp.run();
     }
+
+    fn stop(&self) {
+        self.yt_dlp.stop();
+    }
+
+    fn reconfigure(&self, config: &ModuleConfig) -> bool {
+        self.yt_dlp.apply_config(config.yt_dlp_config())
+    }
 }
@@ -0,0 +1,207 @@
+//! Keeps module threads alive.
+//!
+//! Every module runs in its own thread. If a module panics, the panic is caught,
+//! logged and the module is restarted after a backoff delay.
+//! The restart counts of all modules are logged on every restart and at shutdown,
+//! and [`restart_counts`] makes them available to the rest of hoard.
+
+use std::{
+    any::Any,
+    collections::BTreeMap,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Mutex, PoisonError},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::{shutdown, Module, ModuleConfig};
+
+/// Delay before the first restart, doubled on every consecutive panic
+const BASE_BACKOFF: Duration = Duration::from_secs(5);
+/// Upper limit for the restart delay
+const MAX_BACKOFF: Duration = Duration::from_secs(30 * 60);
+/// A module running this long without panicking resets the backoff
+const STABLE_AFTER: Duration = Duration::from_secs(60 * 60);
+
+/// Restarts after a panic of every running module by name, kept across config reloads
+static RESTARTS: Mutex<BTreeMap<String, usize>> = Mutex::new(BTreeMap::new());
+
+/// A module thread watched by the [`Supervisor`]
+struct Supervised {
+    key: String,
+    name: String,
+    module: Arc<dyn Module>,
+    thread: JoinHandle<()>,
+}
+
+/// Runs modules in their own threads and restarts them if they panic
+#[derive(Default)]
+pub struct Supervisor {
+    modules: Vec<Supervised>,
+    /// Threads of stopped modules which are still finishing their download
+    stopping: Vec<JoinHandle<()>>,
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start a module identified by `key` in a new supervised thread
+    pub fn spawn(&mut self, key: &str, module: Box<dyn Module>) {
+        self.start(key, module, None);
+    }
+
+    /// Stop the module identified by `key` once its current download finished
+    pub fn stop(&mut self, key: &str) -> bool {
+        if let Some(module) = self.modules.iter().find(|m| m.key == key) {
+            restarts().remove(&module.name);
+        }
+        match self.take(key) {
+            Some(thread) => {
+                self.stopping.push(thread);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Replace the module identified by `key`.
+    ///
+    /// The new module starts once the old one finished its current download.
+    pub fn replace(&mut self, key: &str, module: Box<dyn Module>) {
+        let previous = self.take(key);
+        self.start(key, module, previous);
+    }
+
+    /// Apply a changed config to the running module identified by `key`, see [`Module::reconfigure`]
+    ///
+    /// # Returns
+    /// `false` if the module has to be replaced instead
+    pub fn reconfigure(&self, key: &str, config: &ModuleConfig) -> bool {
+        self.modules
+            .iter()
+            .find(|m| m.key == key)
+            .is_some_and(|m| m.module.reconfigure(config))
+    }
+
+    /// Wait for all modules to finish
+    pub fn join(self) {
+        for module in self.modules {
+            if module.thread.join().is_err() {
+                log::error!("Supervisor of {} module died", module.name);
+            }
+        }
+        for thread in self.stopping {
+            let _ = thread.join();
+        }
+
+        for (name, restarts) in restart_counts() {
+            if restarts > 0 {
+                log::warn!("{name} module was restarted {restarts} times after panics");
+            }
+        }
+    }
+
+    fn start(&mut self, key: &str, module: Box<dyn Module>, previous: Option<JoinHandle<()>>) {
+        let name = module.name();
+        let module: Arc<dyn Module> = Arc::from(module);
+        restarts().entry(name.clone()).or_insert(0);
+
+        let thread = {
+            let name = name.clone();
+            let module = module.clone();
+            std::thread::spawn(move || {
+                if let Some(previous) = previous {
+                    let _ = previous.join();
+                }
+                supervise(&name, module.as_ref());
+            })
+        };
+
+        self.modules.push(Supervised {
+            key: key.to_string(),
+            name,
+            module,
+            thread,
+        });
+    }
+
+    /// Stop the module identified by `key` and return its thread
+    fn take(&mut self, key: &str) -> Option<JoinHandle<()>> {
+        let index = self.modules.iter().position(|m| m.key == key)?;
+        let supervised = self.modules.remove(index);
+        supervised.module.stop();
+        Some(supervised.thread)
+    }
+}
+
+/// Lock the restart counts
+fn restarts() -> std::sync::MutexGuard<'static, BTreeMap<String, usize>> {
+    RESTARTS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// How often each running module was restarted after a panic
+pub fn restart_counts() -> Vec<(String, usize)> {
+    restarts()
+        .iter()
+        .map(|(name, restarts)| (name.clone(), *restarts))
+        .collect()
+}
+
+/// Run `module` until it returns normally, restarting it with backoff on panics
+fn supervise(name: &str, module: &dyn Module) {
+    let mut failures: u32 = 0;
+
+    loop {
+        let started = Instant::now();
+        match panic::catch_unwind(AssertUnwindSafe(|| module.run())) {
+            Ok(()) => {
+                log::info!("{name} module stopped");
+                return;
+            }
+            Err(payload) => {
+                if started.elapsed() >= STABLE_AFTER {
+                    failures = 0;
+                }
+                failures += 1;
+                let restarts = {
+                    let mut counts = restarts();
+                    let count = counts.entry(name.to_string()).or_insert(0);
+                    *count += 1;
+                    *count
+                };
+                let backoff = BASE_BACKOFF
+                    .saturating_mul(1 << (failures - 1).min(16))
+                    .min(MAX_BACKOFF);
+
+                log::error!(
+                    "{name} module panicked: {}. Restarting in {}s ({restarts} restarts so far)",
+                    panic_message(payload.as_ref()),
+                    backoff.as_secs()
+                );
+                log::info!("Module restarts: {}", summary(&restart_counts()));
+                if !shutdown::sleep(backoff) {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Format restart counts like `soundcloud: 0, youtube: 2`
+fn summary(counts: &[(String, usize)]) -> String {
+    counts
+        .iter()
+        .map(|(name, restarts)| format!("{name}: {restarts}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
@@ -2,49 +2,57 @@ use std::{collections::HashMap, path::PathBuf};
 
 use serde::{Deserialize, Serialize};
 
-use crate::{yt_dlp::config::YtDlpConfig, yt_dlp::YtDlpModule, Module};
+use crate::{
+    bandwidth::RateLimit,
+    yt_dlp::config::{DownloadOptions, YtDlpConfig},
+    yt_dlp::YtDlpModule,
+    Module, ModuleConfig,
+};
 
 /// Configuration for the `YouTube` Module
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct YouTubeConfig {
     /// Interval in minutes between checks
-    interval: u64,
-    /// Amount of videos to query
-    limit: Option<u64>,
+    pub interval: Option<u64>,
+    /// Number of downloads running at the same time
+    pub concurrency: Option<usize>,
+    /// Modules with a higher priority get a free download slot first
+    pub priority: Option<u32>,
+    /// Bandwidth shared by the downloads of this module
+    pub rate_limit: Option<RateLimit>,
     /// Channels to check
-    channels: HashMap<String, toml::Value>,
-    /// Format of the Thumbnail
-    thumbnail_format: Option<String>,
-    /// Output Template for yt-dlp
-    output_format: Option<String>,
-    /// Download description
-    pub write_description: Option<bool>,
-    /// Download info.json
-    pub write_info_json: Option<bool>,
-    /// Download comments
-    pub write_comments: Option<bool>,
-    /// Download thumbnail
-    pub write_thumbnail: Option<bool>,
-    /// Download subtitles
-    pub write_subs: Option<bool>,
-    /// Embed subtitles
-    pub embed_subs: Option<bool>,
-    /// Embed thumbnail
-    pub embed_thumbnail: Option<bool>,
-    /// Embed metadata
-    pub embed_metadata: Option<bool>,
-    /// Embed chapters
-    embed_chapters: Option<bool>,
-    /// Embed info.json
-    pub embed_info_json: Option<bool>,
-    /// Split by chapter
-    pub split_chapters: Option<bool>,
-    /// Format Selection
-    pub format: Option<String>,
-    /// Cookie File
-    pub cookie: Option<String>,
+    pub channels: HashMap<String, toml::Value>,
     /// Webhooks for notifications
     pub webhooks: Option<Vec<String>>,
+    /// Only report what would be downloaded
+    pub dry_run: Option<bool>,
+    /// Download options for all items
+    #[serde(flatten)]
+    pub options: DownloadOptions,
+}
+
+impl YouTubeConfig {
+    /// The generic `yt-dlp` config with the defaults of this module
+    pub fn yt_dlp_config(&self) -> YtDlpConfig {
+        let defaults = DownloadOptions {
+            write_description: Some(true),
+            write_thumbnail: Some(true),
+            audio_only: Some(false),
+            ..Default::default()
+        };
+
+        YtDlpConfig {
+            name: Some("youtube".to_string()),
+            interval: self.interval,
+            concurrency: self.concurrency,
+            priority: self.priority,
+            rate_limit: self.rate_limit.clone(),
+            items: self.channels.clone(),
+            webhooks: self.webhooks.clone(),
+            dry_run: self.dry_run,
+            options: defaults.merge(&self.options),
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -55,34 +63,7 @@ pub struct YouTubeModule {
 impl YouTubeModule {
     pub fn new(config: YouTubeConfig, db: crate::db::Database, root_dir: PathBuf) -> Self {
         Self {
-            yt_dlp: YtDlpModule::new(
-                YtDlpConfig {
-                    name: Some("youtube".to_string()),
-                    interval: config.interval,
-                    limit: config.limit,
-                    items: config.channels,
-                    thumbnail_format: config.thumbnail_format,
-                    output_format: config.output_format.clone(),
-                    write_description: Some(config.write_description.unwrap_or(true)),
-                    write_info_json: config.write_info_json,
-                    write_comments: config.write_comments,
-                    write_thumbnail: Some(config.write_thumbnail.unwrap_or(true)),
-                    write_subs: config.write_subs,
-                    audio_format: None,
-                    embed_subs: config.embed_subs,
-                    embed_thumbnail: config.embed_thumbnail,
-                    embed_metadata: config.embed_metadata,
-                    embed_chapters: config.embed_chapters,
-                    embed_info_json: config.embed_info_json,
-                    split_chapters: config.split_chapters,
-                    format: config.format,
-                    cookie: config.cookie,
-                    audio_only: Some(false),
-                    webhooks: config.webhooks,
-                },
-                db,
-                root_dir,
-            ),
+            yt_dlp: YtDlpModule::new(config.yt_dlp_config(), db, root_dir),
         }
     }
 }
@@ -92,7 +73,23 @@ impl Module for YouTubeModule {
         "YouTube".to_string()
     }
 
+    fn run_cycle(&self) -> bool {
+        self.yt_dlp.run_cycle()
+    }
+
+    fn run_item(&self, name: &str) -> Option<bool> {
+        self.yt_dlp.run_item(name)
+    }
+
     fn run(&self) {
         self.yt_dlp.run();
     }
+
+    fn stop(&self) {
+        self.yt_dlp.stop();
+    }
+
+    fn reconfigure(&self, config: &ModuleConfig) -> bool {
+        self.yt_dlp.apply_config(config.yt_dlp_config())
+    }
 }
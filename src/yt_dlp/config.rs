@@ -1,18 +1,40 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, path::PathBuf};
 
 use serde::{Deserialize, Serialize};
 
+use crate::bandwidth::RateLimit;
+
 /// Configuration for the `YouTube` Module
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct YtDlpConfig {
     /// Module Name
     pub name: Option<String>,
     /// Interval in minutes between checks
-    pub interval: u64,
-    /// Amount of items to query
-    pub limit: Option<u64>,
+    pub interval: Option<u64>,
+    /// Number of downloads running at the same time
+    pub concurrency: Option<usize>,
+    /// Modules with a higher priority get a free download slot first
+    pub priority: Option<u32>,
+    /// Bandwidth shared by the downloads of this module
+    pub rate_limit: Option<RateLimit>,
     /// Items to check
     pub items: HashMap<String, toml::Value>,
+    /// Webhooks for notifications
+    pub webhooks: Option<Vec<String>>,
+    /// Only report what would be downloaded
+    pub dry_run: Option<bool>,
+    /// Download options for all items
+    #[serde(flatten)]
+    pub options: DownloadOptions,
+}
+
+/// Options for downloading entries, set for a whole module or overridden per item
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DownloadOptions {
+    /// Name of a preset whose options are used unless set here
+    pub preset: Option<String>,
+    /// Amount of items to query
+    pub limit: Option<u64>,
     /// Format of the Thumbnail
     pub thumbnail_format: Option<String>,
     /// Output Template for yt-dlp
@@ -47,6 +69,385 @@ pub struct YtDlpConfig {
     pub format: Option<String>,
     /// Cookie File
     pub cookie: Option<String>,
-    /// Webhooks for notifications
-    pub webhooks: Option<Vec<String>>,
+    /// Give up on an entry after this many failed downloads
+    pub max_attempts: Option<u32>,
+    /// Delay in minutes before retrying a failed download, doubled on every further failure
+    pub retry_delay: Option<u64>,
+    /// Daily time window like `01:00-07:00` to download in, new entries wait for it
+    pub active_hours: Option<String>,
+    /// Cron expression for the checks of a module instead of its `interval`,
+    /// or of an item which is checked at its own times
+    pub schedule: Option<String>,
+}
+
+impl DownloadOptions {
+    /// What downloads use for every option set nowhere in the config
+    pub fn fallbacks() -> Self {
+        Self {
+            limit: Some(10),
+            thumbnail_format: Some("jpg".to_string()),
+            output_format: Some("%(title)s.%(ext)s".to_string()),
+            write_description: Some(false),
+            write_info_json: Some(false),
+            write_comments: Some(false),
+            write_thumbnail: Some(true),
+            write_subs: Some(false),
+            audio_only: Some(false),
+            embed_subs: Some(false),
+            embed_thumbnail: Some(true),
+            embed_metadata: Some(true),
+            embed_chapters: Some(true),
+            embed_info_json: Some(true),
+            split_chapters: Some(false),
+            max_attempts: Some(5),
+            retry_delay: Some(30),
+            ..Default::default()
+        }
+    }
+
+    /// The options downloads actually use, the [`fallbacks`](Self::fallbacks) fill in everything not set
+    pub fn resolved(&self) -> Self {
+        Self::fallbacks().merge(self)
+    }
+
+    /// Layer `other` on top of `self`. Options set in `other` take precedence.
+    pub fn merge(&self, other: &Self) -> Self {
+        Self {
+            preset: other.preset.clone().or_else(|| self.preset.clone()),
+            limit: other.limit.or(self.limit),
+            thumbnail_format: other
+                .thumbnail_format
+                .clone()
+                .or_else(|| self.thumbnail_format.clone()),
+            output_format: other
+                .output_format
+                .clone()
+                .or_else(|| self.output_format.clone()),
+            write_description: other.write_description.or(self.write_description),
+            write_info_json: other.write_info_json.or(self.write_info_json),
+            write_comments: other.write_comments.or(self.write_comments),
+            write_thumbnail: other.write_thumbnail.or(self.write_thumbnail),
+            write_subs: other.write_subs.or(self.write_subs),
+            audio_only: other.audio_only.or(self.audio_only),
+            audio_format: other
+                .audio_format
+                .clone()
+                .or_else(|| self.audio_format.clone()),
+            embed_subs: other.embed_subs.or(self.embed_subs),
+            embed_thumbnail: other.embed_thumbnail.or(self.embed_thumbnail),
+            embed_metadata: other.embed_metadata.or(self.embed_metadata),
+            embed_chapters: other.embed_chapters.or(self.embed_chapters),
+            embed_info_json: other.embed_info_json.or(self.embed_info_json),
+            split_chapters: other.split_chapters.or(self.split_chapters),
+            format: other.format.clone().or_else(|| self.format.clone()),
+            cookie: other.cookie.clone().or_else(|| self.cookie.clone()),
+            max_attempts: other.max_attempts.or(self.max_attempts),
+            retry_delay: other.retry_delay.or(self.retry_delay),
+            active_hours: other
+                .active_hours
+                .clone()
+                .or_else(|| self.active_hours.clone()),
+            schedule: other.schedule.clone().or_else(|| self.schedule.clone()),
+        }
+    }
+}
+
+/// One or more source URLs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Urls {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Urls {
+    pub fn into_vec(self) -> Vec<String> {
+        match self {
+            Self::One(url) => vec![url],
+            Self::Many(urls) => urls,
+        }
+    }
+}
+
+/// An item written as inline table with its own options
+#[derive(Debug, Clone, Deserialize)]
+struct ItemConfig {
+    url: Urls,
+    #[serde(flatten)]
+    options: DownloadOptions,
+}
+
+/// A single item to check, resolved from the `items` table
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Item {
+    /// Name of the item
+    pub name: String,
+    /// Categories the item is nested in, joined by `/`
+    pub category: Option<String>,
+    /// Source URLs of the item
+    pub urls: Vec<String>,
+    /// Download directory relative to the module directory
+    pub dir: PathBuf,
+    /// Effective download options for this item
+    pub options: DownloadOptions,
+}
+
+impl Item {
+    /// Whether `name` is the name of the item or its path, categories and name joined by `/`
+    pub fn matches(&self, name: &str) -> bool {
+        self.name == name
+            || self
+                .category
+                .as_ref()
+                .is_some_and(|category| format!("{category}/{}", self.name) == name)
+    }
+}
+
+impl YtDlpConfig {
+    /// Resolve all items with their effective options.
+    ///
+    /// An item is either
+    /// - a URL string,
+    /// - an array of URL strings,
+    /// - an inline table with a `url` key and option overrides, see [`is_item`],
+    /// - or a table of further items, which makes it a category.
+    ///
+    /// Invalid items are returned as error with their path.
+    pub fn items(&self) -> Vec<Result<Item, String>> {
+        let mut items = Vec::new();
+        collect_items(&self.items, &[], &self.options, &mut items);
+        items
+    }
+}
+
+/// Whether `table` is an item with options rather than a category of items.
+///
+/// An item has a `url` string or array of strings, and none of its other values are
+/// tables or URLs like the items of a category. So a category may hold an item called `url`
+/// next to others, only a category with nothing but a `url` item is taken for an item.
+pub fn is_item(table: &toml::Table) -> bool {
+    let is_urls = |value: &toml::Value| match value {
+        toml::Value::String(url) => url.contains("://"),
+        toml::Value::Array(urls) => urls
+            .iter()
+            .any(|url| url.as_str().is_some_and(|url| url.contains("://"))),
+        _ => false,
+    };
+    let url = match table.get("url") {
+        Some(toml::Value::String(_)) => true,
+        Some(toml::Value::Array(urls)) => urls.iter().all(toml::Value::is_str),
+        _ => false,
+    };
+    url && table
+        .iter()
+        .all(|(key, value)| key == "url" || !(value.is_table() || is_urls(value)))
+}
+
+fn collect_items(
+    table: &HashMap<String, toml::Value>,
+    categories: &[&str],
+    options: &DownloadOptions,
+    items: &mut Vec<Result<Item, String>>,
+) {
+    for (name, value) in table {
+        let path: Vec<&str> = categories.iter().copied().chain([name.as_str()]).collect();
+
+        let (urls, item_options) = match value {
+            toml::Value::String(_) | toml::Value::Array(_) => {
+                match value.clone().try_into::<Urls>() {
+                    Ok(urls) => (urls, DownloadOptions::default()),
+                    Err(_) => {
+                        items.push(Err(format!(
+                            "Item \"{}\" must be a URL or an array of URLs",
+                            path.join(".")
+                        )));
+                        continue;
+                    }
+                }
+            }
+            toml::Value::Table(table) if is_item(table) => {
+                match value.clone().try_into::<ItemConfig>() {
+                    Ok(item) => (item.url, item.options),
+                    Err(e) => {
+                        items.push(Err(format!("Item \"{}\": {e}", path.join("."))));
+                        continue;
+                    }
+                }
+            }
+            toml::Value::Table(table) => {
+                let table: HashMap<String, toml::Value> = table.clone().into_iter().collect();
+                collect_items(&table, &path, options, items);
+                continue;
+            }
+            _ => {
+                items.push(Err(format!("Item \"{}\" is not a URL", path.join("."))));
+                continue;
+            }
+        };
+
+        items.push(Ok(Item {
+            name: name.clone(),
+            category: (!categories.is_empty()).then(|| categories.join("/")),
+            urls: urls.into_vec(),
+            dir: path.iter().collect(),
+            options: options.merge(&item_options),
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module(toml: &str) -> YtDlpConfig {
+        toml::from_str(toml).unwrap()
+    }
+
+    /// Resolved items sorted by path
+    fn items(config: &YtDlpConfig) -> Vec<Item> {
+        let mut items: Vec<Item> = config.items().into_iter().map(Result::unwrap).collect();
+        items.sort_by(|a, b| a.dir.cmp(&b.dir));
+        items
+    }
+
+    #[test]
+    fn merge_prefers_other() {
+        let base = DownloadOptions {
+            limit: Some(10),
+            format: Some("best".to_string()),
+            audio_only: Some(false),
+            ..Default::default()
+        };
+        let other = DownloadOptions {
+            limit: Some(50),
+            audio_only: Some(true),
+            cookie: Some("cookies.txt".to_string()),
+            ..Default::default()
+        };
+
+        let merged = base.merge(&other);
+        assert_eq!(merged.limit, Some(50));
+        assert_eq!(merged.format.as_deref(), Some("best"));
+        assert_eq!(merged.audio_only, Some(true));
+        assert_eq!(merged.cookie.as_deref(), Some("cookies.txt"));
+        assert_eq!(merged.write_subs, None);
+        assert_eq!(base.merge(&DownloadOptions::default()), base);
+    }
+
+    #[test]
+    fn resolved_fills_in_fallbacks() {
+        let options = DownloadOptions {
+            limit: Some(3),
+            embed_thumbnail: Some(false),
+            ..Default::default()
+        }
+        .resolved();
+        assert_eq!(options.limit, Some(3));
+        assert_eq!(options.embed_thumbnail, Some(false));
+        assert_eq!(options.write_thumbnail, Some(true));
+        assert_eq!(options.thumbnail_format.as_deref(), Some("jpg"));
+        assert_eq!(options.output_format.as_deref(), Some("%(title)s.%(ext)s"));
+        assert_eq!(options.max_attempts, Some(5));
+        assert_eq!(options.retry_delay, Some(30));
+        assert_eq!(options.format, None);
+    }
+
+    #[test]
+    fn items_of_every_shape() {
+        let config = module(
+            r#"
+            limit = 10
+            format = "best"
+            [items]
+            Plain = "https://example.com/plain"
+            Many = ["https://example.com/a", "https://example.com/b"]
+            Inline = { url = "https://example.com/inline", limit = 50, audio_only = true }
+            [items.Music.Live]
+            Deep = "https://example.com/deep"
+            "#,
+        );
+
+        let items = items(&config);
+        let names: Vec<&str> = items.iter().map(|item| item.name.as_str()).collect();
+        assert_eq!(names, ["Inline", "Many", "Deep", "Plain"]);
+
+        let inline = &items[0];
+        assert_eq!(inline.urls, ["https://example.com/inline"]);
+        assert_eq!(inline.options.limit, Some(50));
+        assert_eq!(inline.options.audio_only, Some(true));
+        assert_eq!(inline.options.format.as_deref(), Some("best"));
+        assert_eq!(inline.category, None);
+
+        assert_eq!(items[1].urls.len(), 2);
+        assert_eq!(items[1].options.limit, Some(10));
+
+        let deep = &items[2];
+        assert_eq!(deep.category.as_deref(), Some("Music/Live"));
+        assert_eq!(deep.dir, PathBuf::from("Music/Live/Deep"));
+    }
+
+    #[test]
+    fn category_with_item_called_url() {
+        let config = module(
+            r#"
+            [items.Links]
+            url = "https://example.com/url"
+            other = "https://example.com/other"
+            "#,
+        );
+
+        let items = items(&config);
+        assert_eq!(items.len(), 2);
+        assert!(items
+            .iter()
+            .all(|item| item.category.as_deref() == Some("Links")));
+        assert_eq!(items[1].name, "url");
+        assert_eq!(items[1].urls, ["https://example.com/url"]);
+    }
+
+    #[test]
+    fn invalid_items() {
+        let config = module(
+            r#"
+            [items]
+            Number = 3
+            Mixed = ["https://example.com", 3]
+            Typo = { url = "https://example.com", limit = "many" }
+            "#,
+        );
+        let items = config.items();
+        assert_eq!(items.len(), 3);
+        assert!(items.iter().all(Result::is_err));
+    }
+
+    #[test]
+    fn match_by_name_or_path() {
+        let config = module(
+            r#"
+            [items]
+            Plain = "https://example.com/plain"
+            Inline = { url = "https://example.com/inline", limit = 5 }
+            [items.Music]
+            Deep = "https://example.com/deep"
+            Other = "https://example.com/other"
+            "#,
+        );
+        let items = items(&config);
+        let matching = |name: &str| -> Vec<&str> {
+            let mut matching: Vec<_> = items
+                .iter()
+                .filter(|item| item.matches(name))
+                .map(|item| item.name.as_str())
+                .collect();
+            matching.sort_unstable();
+            matching
+        };
+
+        assert_eq!(matching("Inline"), ["Inline"]);
+        assert!(matching("Missing").is_empty());
+        assert_eq!(matching("Music/Deep"), ["Deep"]);
+        assert_eq!(matching("Deep"), ["Deep"]);
+        assert!(matching("Music").is_empty());
+        assert!(matching("Other/Deep").is_empty());
+    }
 }
@@ -0,0 +1,207 @@
+use serde::{Deserialize, Serialize};
+
+/// Raw listing entry as printed by `yt-dlp --dump-json --flat-playlist`.
+///
+/// Flat playlist entries and fully extracted videos use slightly different keys,
+/// so everything is optional here and normalized into an [`Entry`].
+#[derive(Debug, Deserialize)]
+struct RawEntry {
+    id: Option<String>,
+    ie_key: Option<String>,
+    extractor_key: Option<String>,
+    title: Option<String>,
+    webpage_url: Option<String>,
+    url: Option<String>,
+    upload_date: Option<String>,
+    duration: Option<f64>,
+    live_status: Option<String>,
+    availability: Option<String>,
+}
+
+/// A single media entry found by `yt-dlp`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Media ID as reported by the extractor
+    pub id: String,
+    /// Lowercase extractor key (e.g. `youtube`)
+    pub extractor: String,
+    /// Title of the entry
+    pub title: String,
+    /// URL of the entry
+    pub url: String,
+    /// Upload date in `YYYYMMDD` format
+    pub upload_date: Option<String>,
+    /// Duration in seconds
+    pub duration: Option<f64>,
+    /// Live status (`not_live`, `is_live`, `is_upcoming`, `was_live`, `post_live`)
+    pub live_status: Option<String>,
+    /// Availability (`public`, `unlisted`, `private`, `needs_auth`, `subscriber_only`, `premium_only`)
+    pub availability: Option<String>,
+}
+
+impl Entry {
+    /// Parse a single JSON object as printed by `yt-dlp --dump-json`.
+    ///
+    /// # Returns
+    ///
+    /// An error message if the JSON is malformed or lacks an ID or URL.
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let raw: RawEntry = serde_json::from_str(json).map_err(|e| e.to_string())?;
+
+        let id = raw.id.ok_or_else(|| "entry has no id".to_string())?;
+        // `ie_key` is the extractor of the entry itself, `extractor_key` may be the one of the playlist
+        let extractor = raw
+            .ie_key
+            .or(raw.extractor_key)
+            .unwrap_or_else(|| "generic".to_string())
+            .to_lowercase();
+        let url = raw
+            .webpage_url
+            .or(raw.url)
+            .ok_or_else(|| format!("entry \"{id}\" has no url"))?;
+
+        Ok(Self {
+            title: raw.title.unwrap_or_else(|| id.clone()),
+            id,
+            extractor,
+            url,
+            upload_date: raw.upload_date,
+            duration: raw.duration,
+            live_status: raw.live_status,
+            availability: raw.availability,
+        })
+    }
+
+    /// Parse the line delimited output of `yt-dlp --dump-json`.
+    ///
+    /// Lines which can not be parsed are logged and skipped.
+    pub fn parse_listing(output: &str) -> Vec<Self> {
+        output
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| match Self::from_json(line) {
+                Ok(entry) => Some(entry),
+                Err(e) => {
+                    log::warn!("Skipping malformed yt-dlp entry: {e}");
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Whether this entry is a scheduled stream or premiere which can not be downloaded yet
+    pub fn is_upcoming(&self) -> bool {
+        self.live_status.as_deref() == Some("is_upcoming")
+    }
+}
+
+/// Try to derive the canonical identity (extractor key and media ID) from a URL alone.
+///
+/// This only knows about URL schemes where the media ID is part of the URL
+/// (`youtube.com/watch?v=`, `youtu.be/`, `/shorts/`, `/live/`, `/embed/`).
+/// Everything else needs `yt-dlp` to resolve the identity.
+pub fn media_id_from_url(url: &str) -> Option<(String, String)> {
+    let url = reqwest::Url::parse(url).ok()?;
+    let host = url.host_str()?.trim_start_matches("www.");
+
+    let id = match host {
+        "youtu.be" => url.path_segments()?.next().map(ToString::to_string),
+        "youtube.com" | "m.youtube.com" | "music.youtube.com" | "youtube-nocookie.com" => {
+            let mut segments = url.path_segments()?;
+            match segments.next()? {
+                "watch" => url
+                    .query_pairs()
+                    .find(|(key, _)| key == "v")
+                    .map(|(_, v)| v.to_string()),
+                "shorts" | "live" | "embed" | "v" => segments.next().map(ToString::to_string),
+                _ => None,
+            }
+        }
+        _ => None,
+    }?;
+
+    let valid = id.len() == 11
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    valid.then(|| ("youtube".to_string(), id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_playlist_entry() {
+        let entry = Entry::from_json(
+            r#"{"id": "dQw4w9WgXcQ", "ie_key": "Youtube", "title": "Title", "url": "https://www.youtube.com/watch?v=dQw4w9WgXcQ", "duration": 212.0, "live_status": null}"#,
+        )
+        .unwrap();
+        assert_eq!(entry.id, "dQw4w9WgXcQ");
+        assert_eq!(entry.extractor, "youtube");
+        assert_eq!(entry.title, "Title");
+        assert_eq!(entry.url, "https://www.youtube.com/watch?v=dQw4w9WgXcQ");
+        assert_eq!(entry.duration, Some(212.0));
+        assert_eq!(entry.upload_date, None);
+    }
+
+    #[test]
+    fn full_entry_prefers_webpage_url() {
+        let entry = Entry::from_json(
+            r#"{"id": "123", "extractor_key": "Soundcloud", "webpage_url": "https://soundcloud.com/a/b", "url": "https://cdn.example.com/123.mp3", "upload_date": "20240301"}"#,
+        )
+        .unwrap();
+        assert_eq!(entry.extractor, "soundcloud");
+        assert_eq!(entry.url, "https://soundcloud.com/a/b");
+        assert_eq!(entry.upload_date.as_deref(), Some("20240301"));
+        // falls back to the ID without a title
+        assert_eq!(entry.title, "123");
+    }
+
+    #[test]
+    fn missing_id_or_url() {
+        assert!(Entry::from_json(r#"{"url": "https://example.com"}"#).is_err());
+        assert!(Entry::from_json(r#"{"id": "abc"}"#).is_err());
+        assert!(Entry::from_json("not json").is_err());
+    }
+
+    #[test]
+    fn listing_skips_malformed_lines() {
+        let output = concat!(
+            r#"{"id": "a", "title": "Line\nbreak", "url": "https://example.com/a"}"#,
+            "\n\n",
+            "garbage\n",
+            r#"{"id": "b"}"#,
+            "\n",
+            r#"{"id": "c", "url": "https://example.com/c", "live_status": "is_upcoming"}"#,
+            "\n",
+        );
+        let entries = Entry::parse_listing(output);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].title, "Line\nbreak");
+        assert_eq!(entries[0].extractor, "generic");
+        assert!(!entries[0].is_upcoming());
+        assert_eq!(entries[1].id, "c");
+        assert!(entries[1].is_upcoming());
+    }
+
+    #[test]
+    fn media_ids_from_youtube_urls() {
+        let youtube = |id: &str| Some(("youtube".to_string(), id.to_string()));
+        assert_eq!(
+            media_id_from_url("https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=1"),
+            youtube("dQw4w9WgXcQ")
+        );
+        assert_eq!(
+            media_id_from_url("https://youtu.be/dQw4w9WgXcQ"),
+            youtube("dQw4w9WgXcQ")
+        );
+        assert_eq!(
+            media_id_from_url("https://youtube.com/shorts/dQw4w9WgXcQ"),
+            youtube("dQw4w9WgXcQ")
+        );
+        assert_eq!(media_id_from_url("https://youtube.com/@channel"), None);
+        assert_eq!(media_id_from_url("https://youtube.com/watch?v=short"), None);
+        assert_eq!(media_id_from_url("https://soundcloud.com/a/b"), None);
+    }
+}
@@ -0,0 +1,320 @@
+use std::fmt;
+
+/// Classified error of a `yt-dlp` invocation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DownloadError {
+    /// The media was removed, made private or never existed
+    Unavailable(String),
+    /// The media is only available to channel members or paying subscribers
+    MembersOnly(String),
+    /// The media requires a login to confirm the age
+    AgeRestricted(String),
+    /// The media is not available in this country
+    GeoBlocked(String),
+    /// The site is rate limiting us (HTTP 429 or bot checks)
+    RateLimited(String),
+    /// Connection problems or server errors
+    Network(String),
+    /// The requested format does not exist for this media
+    FormatUnavailable(String),
+    /// No space left to write the download
+    DiskFull(String),
+    /// `yt-dlp` or one of its helpers (`ffmpeg`, `aria2c`) is not installed
+    ToolMissing(String),
+    /// `yt-dlp` was stopped because hoard is shutting down
+    Interrupted(String),
+    /// Anything we could not classify
+    Other(String),
+}
+
+/// Patterns in lowercase `yt-dlp` output for each error kind, checked in order
+const PATTERNS: &[(&str, &[&str])] = &[
+    (
+        "disk_full",
+        &["no space left on device", "errno 28", "disk quota exceeded"],
+    ),
+    (
+        "tool_missing",
+        &[
+            "ffmpeg not found",
+            "ffprobe not found",
+            "ffmpeg is not installed",
+            "aria2c not found",
+            "external downloader cannot be used",
+        ],
+    ),
+    (
+        "rate_limited",
+        &[
+            "http error 429",
+            "too many requests",
+            "rate-limit",
+            "rate limit",
+            "confirm you're not a bot",
+            "confirm you’re not a bot",
+        ],
+    ),
+    (
+        "members_only",
+        &[
+            "members-only",
+            "members only",
+            "join this channel",
+            "available to this channel's members",
+            "requires a subscription",
+            "premium members",
+        ],
+    ),
+    (
+        "age_restricted",
+        &[
+            "confirm your age",
+            "age-restricted",
+            "age restricted",
+            "inappropriate for some users",
+        ],
+    ),
+    (
+        "geo_blocked",
+        &[
+            "available in your country",
+            "geo restricted",
+            "geo-restricted",
+            "blocked it in your country",
+            "not available from your location",
+        ],
+    ),
+    (
+        "format_unavailable",
+        &[
+            "requested format is not available",
+            "no video formats found",
+        ],
+    ),
+    (
+        "unavailable",
+        &[
+            "private video",
+            "video is private",
+            "video unavailable",
+            "has been removed",
+            "no longer available",
+            "has been terminated",
+            // extractor messages only, a missing cookie file or directory is a local problem
+            "video does not exist",
+            "channel does not exist",
+            "playlist does not exist",
+            "user does not exist",
+            "account does not exist",
+            "track does not exist",
+            "http error 404",
+            "http error 410",
+        ],
+    ),
+    (
+        "network",
+        &[
+            "unable to download webpage",
+            "unable to download api page",
+            "connection reset",
+            "connection refused",
+            "connection aborted",
+            "timed out",
+            "temporary failure in name resolution",
+            "name or service not known",
+            "network is unreachable",
+            "urlopen error",
+            "incompleteread",
+            "http error 5",
+        ],
+    ),
+];
+
+impl DownloadError {
+    /// Classify the stderr output of a failed `yt-dlp` run.
+    ///
+    /// Only the `ERROR:` lines are kept as message if there are any.
+    pub fn from_stderr(stderr: &str) -> Self {
+        let errors: Vec<&str> = stderr
+            .lines()
+            .map(str::trim)
+            .filter(|line| line.starts_with("ERROR:"))
+            .collect();
+        let message = if errors.is_empty() {
+            stderr.trim().to_string()
+        } else {
+            errors.join("\n")
+        };
+
+        let haystack = stderr.to_lowercase();
+        let kind = PATTERNS
+            .iter()
+            .find(|(_, patterns)| patterns.iter().any(|p| haystack.contains(p)))
+            .map_or("other", |(kind, _)| kind);
+
+        Self::from_kind(kind, message)
+    }
+
+    /// Classify an error from spawning a process
+    pub fn from_io(tool: &str, e: &std::io::Error) -> Self {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            Self::ToolMissing(format!("{tool} is not installed"))
+        } else {
+            Self::Other(format!("Failed to execute {tool}: {e}"))
+        }
+    }
+
+    /// Rebuild an error from its [`kind`](Self::kind) and message
+    pub fn from_kind(kind: &str, message: String) -> Self {
+        match kind {
+            "unavailable" => Self::Unavailable(message),
+            "members_only" => Self::MembersOnly(message),
+            "age_restricted" => Self::AgeRestricted(message),
+            "geo_blocked" => Self::GeoBlocked(message),
+            "rate_limited" => Self::RateLimited(message),
+            "network" => Self::Network(message),
+            "format_unavailable" => Self::FormatUnavailable(message),
+            "disk_full" => Self::DiskFull(message),
+            "tool_missing" => Self::ToolMissing(message),
+            "interrupted" => Self::Interrupted(message),
+            _ => Self::Other(message),
+        }
+    }
+
+    /// Short identifier of the error category
+    pub const fn kind(&self) -> &'static str {
+        match self {
+            Self::Unavailable(_) => "unavailable",
+            Self::MembersOnly(_) => "members_only",
+            Self::AgeRestricted(_) => "age_restricted",
+            Self::GeoBlocked(_) => "geo_blocked",
+            Self::RateLimited(_) => "rate_limited",
+            Self::Network(_) => "network",
+            Self::FormatUnavailable(_) => "format_unavailable",
+            Self::DiskFull(_) => "disk_full",
+            Self::ToolMissing(_) => "tool_missing",
+            Self::Interrupted(_) => "interrupted",
+            Self::Other(_) => "other",
+        }
+    }
+
+    /// The error message as reported by `yt-dlp`
+    pub fn message(&self) -> &str {
+        match self {
+            Self::Unavailable(msg)
+            | Self::MembersOnly(msg)
+            | Self::AgeRestricted(msg)
+            | Self::GeoBlocked(msg)
+            | Self::RateLimited(msg)
+            | Self::Network(msg)
+            | Self::FormatUnavailable(msg)
+            | Self::DiskFull(msg)
+            | Self::ToolMissing(msg)
+            | Self::Interrupted(msg)
+            | Self::Other(msg) => msg,
+        }
+    }
+
+    /// Retrying will never succeed without changing the config, so don't bother
+    pub const fn is_permanent(&self) -> bool {
+        matches!(
+            self,
+            Self::Unavailable(_)
+                | Self::MembersOnly(_)
+                | Self::AgeRestricted(_)
+                | Self::GeoBlocked(_)
+                | Self::FormatUnavailable(_)
+        )
+    }
+
+    /// The problem is on our side and would fail every other download as well.
+    ///
+    /// These do not count as a failed attempt for the entry.
+    pub const fn is_local(&self) -> bool {
+        matches!(
+            self,
+            Self::DiskFull(_) | Self::ToolMissing(_) | Self::Interrupted(_)
+        )
+    }
+
+    /// Further requests to the site should stop for now
+    pub const fn should_stop(&self) -> bool {
+        self.is_local() || matches!(self, Self::RateLimited(_))
+    }
+}
+
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}", self.kind(), self.message())
+    }
+}
+
+impl std::error::Error for DownloadError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kind(stderr: &str) -> &'static str {
+        DownloadError::from_stderr(stderr).kind()
+    }
+
+    #[test]
+    fn classifies_every_kind() {
+        let cases = [
+            ("ERROR: [youtube] abc: Private video. Sign in if you've been granted access", "unavailable"),
+            ("ERROR: [youtube] abc: Video unavailable. This video has been removed by the uploader", "unavailable"),
+            ("ERROR: [youtube:tab] This channel does not exist.", "unavailable"),
+            ("ERROR: [youtube] abc: Join this channel to get access to members-only content", "members_only"),
+            ("ERROR: [youtube] abc: Sign in to confirm your age. This video may be inappropriate for some users.", "age_restricted"),
+            ("ERROR: [youtube] abc: The uploader has not made this video available in your country", "geo_blocked"),
+            ("ERROR: [youtube] abc: Video is geo restricted", "geo_blocked"),
+            ("ERROR: unable to download video data: HTTP Error 429: Too Many Requests", "rate_limited"),
+            ("ERROR: [youtube] abc: Sign in to confirm you're not a bot", "rate_limited"),
+            ("ERROR: [youtube] abc: Unable to download webpage: <urlopen error [Errno -3] Temporary failure in name resolution>", "network"),
+            ("ERROR: unable to download video data: HTTP Error 503: Service Unavailable", "network"),
+            ("ERROR: [youtube] abc: Requested format is not available. Use --list-formats", "format_unavailable"),
+            ("ERROR: unable to write data: [Errno 28] No space left on device", "disk_full"),
+            ("ERROR: Postprocessing: ffprobe and ffmpeg not found. Please install or provide the path", "tool_missing"),
+            ("ERROR: something nobody has seen before", "other"),
+        ];
+        for (stderr, expected) in cases {
+            assert_eq!(kind(stderr), expected, "{stderr}");
+        }
+    }
+
+    #[test]
+    fn local_missing_files_are_not_unavailable() {
+        assert_eq!(
+            kind("ERROR: cookies file /run/secrets/cookies.txt does not exist"),
+            "other"
+        );
+        assert_eq!(
+            kind("ERROR: unable to open for writing: [Errno 2] directory /data/x does not exist"),
+            "other"
+        );
+    }
+
+    #[test]
+    fn message_keeps_only_error_lines() {
+        let error = DownloadError::from_stderr(
+            "WARNING: [youtube] falling back\nERROR: [youtube] abc: Private video\n",
+        );
+        assert_eq!(error.message(), "ERROR: [youtube] abc: Private video");
+        assert!(error.is_permanent());
+
+        let error = DownloadError::from_stderr("  Traceback without an error line  ");
+        assert_eq!(error.message(), "Traceback without an error line");
+    }
+
+    #[test]
+    fn kind_round_trips() {
+        let error = DownloadError::RateLimited("slow down".to_string());
+        assert_eq!(
+            DownloadError::from_kind(error.kind(), error.message().to_string()),
+            error
+        );
+        assert!(error.should_stop());
+        assert!(!error.is_permanent());
+    }
+}
@@ -1,238 +1,1189 @@
 use std::{
-    io::{BufRead, BufReader},
-    path::PathBuf,
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
     process::Command,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex, PoisonError, RwLock, RwLockReadGuard,
+    },
 };
 
+use chrono::{DateTime, Local};
+use serde::Deserialize;
+
 pub mod config;
-use config::YtDlpConfig;
+pub mod entry;
+pub mod error;
+use config::{DownloadOptions, Item, YtDlpConfig};
+use entry::Entry;
+use error::DownloadError;
+
+use crate::{
+    bandwidth,
+    db::{Attempt, Download, QueueState, QueuedEntry},
+    queue,
+    schedule::{self, TimeWindow},
+    shutdown, slots, Module, ModuleConfig,
+};
+
+/// Information printed by `yt-dlp` after a file was moved to its final location
+#[derive(Debug, Deserialize)]
+struct MovedFile {
+    filepath: Option<PathBuf>,
+    upload_date: Option<String>,
+    duration: Option<f64>,
+}
+
+/// A queued entry waiting for a download worker
+struct Job {
+    /// Item the entry was found under, with its download options
+    item: Item,
+    queued: QueuedEntry,
+}
 
-use crate::{ensure_dir_exists, Module};
+/// Which items a pass of [`YtDlpModule::run_jobs`] checks for new entries
+enum Check {
+    /// Every item, like `hoard run-once`
+    All,
+    /// Every item without a schedule of its own, a regular cycle of the module
+    Regular,
+    /// Only the items with these directories, added or changed by a reload
+    Only(HashSet<PathBuf>),
+    /// Only the items with this name or path, like `hoard run-once --item`.
+    /// Queued entries of other items are left for their own runs.
+    Item(String),
+    /// None, only queued entries are downloaded
+    Queued,
+}
+
+/// Hands the jobs of a cycle to the download workers
+struct Jobs {
+    sender: Sender<Job>,
+    /// Queue IDs handed out already
+    sent: HashSet<i64>,
+}
 
 #[derive(Clone)]
 pub struct YtDlpModule {
-    config: YtDlpConfig,
+    /// Items and options can change while running, see [`YtDlpModule::reconfigure`]
+    config: Arc<RwLock<YtDlpConfig>>,
     db: crate::db::Database,
     root_dir: PathBuf,
+    stopped: Arc<AtomicBool>,
+    /// When the earliest download postponed to its `active_hours` may start
+    postponed: Arc<Mutex<Option<DateTime<Local>>>>,
+    /// Queue IDs handed to the workers, other pending ones were added while the module was busy
+    dispatched: Arc<Mutex<HashSet<i64>>>,
+    /// [`queue::added`] when the last pass read the queue
+    seen_added: Arc<AtomicU64>,
+    /// Directories of items added or changed by a reload which were not checked yet
+    recheck: Arc<Mutex<HashSet<PathBuf>>>,
 }
 
 impl YtDlpModule {
-    pub const fn new(config: YtDlpConfig, db: crate::db::Database, root_dir: PathBuf) -> Self {
+    pub fn new(config: YtDlpConfig, db: crate::db::Database, root_dir: PathBuf) -> Self {
         Self {
-            config,
+            config: Arc::new(RwLock::new(config)),
             db,
             root_dir,
+            stopped: Arc::new(AtomicBool::new(false)),
+            postponed: Arc::new(Mutex::new(None)),
+            dispatched: Arc::new(Mutex::new(HashSet::new())),
+            seen_added: Arc::new(AtomicU64::new(0)),
+            recheck: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    fn config(&self) -> RwLockReadGuard<'_, YtDlpConfig> {
+        self.config.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Apply a changed config to the running module.
+    ///
+    /// Items, options, webhooks, the priority and the rate limit change in place. New and changed
+    /// items are checked right away, removed items are not checked from the next cycle on and
+    /// running downloads finish. Other changes need a restart of the module.
+    ///
+    /// # Returns
+    ///
+    /// `false` if the module has to be restarted for the change.
+    pub fn apply_config(&self, config: YtDlpConfig) -> bool {
+        let mut current = self.config.write().unwrap_or_else(PoisonError::into_inner);
+        let fixed = |c: &YtDlpConfig| {
+            (
+                c.name.clone(),
+                c.interval,
+                c.options.schedule.clone(),
+                c.concurrency,
+                c.dry_run,
+            )
+        };
+        if fixed(&current) != fixed(&config) {
+            return false;
+        }
+
+        let old: Vec<Item> = current.items().into_iter().flatten().collect();
+        let new: Vec<Item> = config.items().into_iter().flatten().collect();
+        let changed: HashSet<PathBuf> = new
+            .iter()
+            .filter(|item| !old.contains(item))
+            .map(|item| item.dir.clone())
+            .collect();
+        let removed = old
+            .iter()
+            .filter(|item| !new.iter().any(|new| new.dir == item.dir))
+            .count();
+        *current = config;
+        drop(current);
+
+        log::info!(
+            "Reconfigured {}: {} items added or changed, {removed} removed",
+            self.name(),
+            changed.len()
+        );
+        if !changed.is_empty() {
+            self.recheck
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .extend(changed);
+            shutdown::wake();
+        }
+        true
+    }
+
+    /// Whether the module should not start any new work
+    fn stopping(&self) -> bool {
+        shutdown::requested() || self.stopped.load(Ordering::SeqCst)
+    }
+
+    /// Remember that a download waits until `time`, so the next cycle starts early enough
+    fn postpone_until(&self, time: DateTime<Local>) {
+        let mut postponed = self
+            .postponed
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if postponed.is_none_or(|postponed| time < postponed) {
+            *postponed = Some(time);
+        }
+    }
+
+    /// When the next cycle starts: at the next time of the schedule or after the interval,
+    /// unless postponed downloads may start earlier
+    fn next_cycle(&self, schedule: Option<&cron::Schedule>) -> DateTime<Local> {
+        let now = Local::now();
+        let next = schedule
+            .and_then(|schedule| schedule.after(&now).next())
+            .unwrap_or_else(|| {
+                now + chrono::Duration::minutes(self.config().interval.unwrap_or(60) as i64)
+            });
+
+        match *self
+            .postponed
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+        {
+            Some(postponed) if postponed < next => postponed,
+            _ => next,
         }
     }
 
-    fn check_item(&self, item: &str, item_url: &str, cwd: &PathBuf) {
-        log::info!("Fetching \"{item}\" videos");
-        match Self::get_latest_entries(item_url, self.config.limit.unwrap_or(10)) {
-            Ok(latest_videos) => {
-                for (video_title, video_url) in latest_videos {
-                    if self.db.check_for_url(&video_url) {
-                        log::trace!("Skipping \"{video_title}\" because it was already downloaded");
-                    } else {
-                        match self.download(&video_url, cwd) {
-                            Ok(()) => {
-                                // mark as downloaded
-                                self.db.insert_url(&video_url);
-                                self.db.update_new_downloads(&self.name(), item, item_url);
-                                log::info!("Downloaded \"{video_title}\"");
-                                self.webhook_notify(&video_url, &video_title, item, true);
-                            }
-                            Err(e) => {
-                                log::error!("Error downloading \"{video_title}\"; Reason: {e}");
-                                self.webhook_notify(&video_url, &video_title, item, false);
-                            }
+    /// Items with a schedule other than the one of the module, by directory
+    fn item_schedules(&self) -> Vec<(PathBuf, String, cron::Schedule)> {
+        let config = self.config();
+        config
+            .items()
+            .into_iter()
+            .flatten()
+            .filter(|item| item.options.schedule != config.options.schedule)
+            .filter_map(|item| {
+                let expression = item.options.schedule?;
+                match schedule::parse_schedule(&expression) {
+                    Ok(schedule) => Some((item.dir, expression, schedule)),
+                    Err(e) => {
+                        log::error!("Ignoring schedule of \"{}\": {e}", item.name);
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// List the new entries of an item, add them to the download queue and hand them to the workers.
+    ///
+    /// # Returns
+    ///
+    /// The number of failed fetches, or an error if the module should stop
+    /// checking items for this cycle, because of rate limiting.
+    fn check_item(&self, item: &Item, jobs: &mut Jobs) -> Result<usize, DownloadError> {
+        log::info!("Fetching \"{}\" videos", item.name);
+
+        let mut failures = 0;
+        let mut postponed = 0;
+        // all sources of an item share one dedup scope
+        let mut seen = HashSet::new();
+        let mut latest_videos = Vec::new();
+        for item_url in &item.urls {
+            if self.stopping() {
+                return Err(DownloadError::Interrupted("Stopping".to_string()));
+            }
+            match Self::get_latest_entries(item_url, item.options.resolved().limit) {
+                Ok(entries) => {
+                    for entry in entries {
+                        if seen.insert((entry.extractor.clone(), entry.id.clone())) {
+                            latest_videos.push((item_url, entry));
                         }
                     }
                 }
+                Err(e) => {
+                    log::error!(
+                        "Could not get videos from \"{}\" ({item_url}). Reason: {e}",
+                        item.name
+                    );
+                    if e.should_stop() {
+                        return Err(e);
+                    }
+                    failures += 1;
+                }
+            }
+        }
+
+        for (item_url, entry) in latest_videos {
+            if self.db.check_for_url(&entry) {
+                log::trace!(
+                    "Skipping \"{}\" because it was already downloaded",
+                    entry.title
+                );
+                continue;
+            }
+            if entry.is_upcoming() {
+                log::trace!("Skipping \"{}\" because it is not out yet", entry.title);
+                continue;
+            }
+
+            let attempt = self.db.get_attempt(&entry);
+            if let Some(attempt) = &attempt {
+                if attempt.failed {
+                    log::trace!(
+                        "Skipping \"{}\" because it failed {} times",
+                        entry.title,
+                        attempt.attempts
+                    );
+                    continue;
+                }
+                if attempt.next_attempt > chrono::Local::now() {
+                    log::trace!(
+                        "Skipping \"{}\" until {}",
+                        entry.title,
+                        attempt.next_attempt
+                    );
+                    continue;
+                }
+            }
+
+            let mut queued = QueuedEntry {
+                id: 0,
+                module: self.name(),
+                item: item.name.clone(),
+                category: item.category.clone(),
+                dir: item.dir.clone(),
+                item_url: item_url.clone(),
+                entry,
+            };
+            // a dry run leaves the queue alone
+            if !self.dry_run() {
+                queued.id = self.db.enqueue(&queued).unwrap_or_default();
+            }
+
+            let job = Job {
+                item: item.clone(),
+                queued,
+            };
+            if !self.dispatch(jobs, job) {
+                postponed += 1;
+            }
+        }
+
+        if postponed > 0 {
+            log::info!(
+                "Postponing {postponed} new entries of \"{}\" until {}",
+                item.name,
+                item.options.active_hours.as_deref().unwrap_or_default()
+            );
+        }
+
+        Ok(failures)
+    }
+
+    /// Hand a job to the workers, unless its item is outside of its `active_hours`.
+    ///
+    /// # Returns
+    ///
+    /// `false` if the download was postponed, it stays in the queue for a later cycle.
+    fn dispatch(&self, jobs: &mut Jobs, job: Job) -> bool {
+        if job.queued.id != 0 {
+            self.dispatched
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(job.queued.id);
+        }
+        let window = job.item.options.active_hours.as_deref().and_then(|window| {
+            window
+                .parse::<TimeWindow>()
+                .map_err(|e| log::error!("Ignoring active hours of \"{}\": {e}", job.item.name))
+                .ok()
+        });
+        if let Some(window) = window {
+            let now = Local::now();
+            if !window.contains(now.time()) {
+                self.postpone_until(window.next_start(now));
+                return false;
+            }
+        }
+
+        if job.queued.id == 0 || jobs.sent.insert(job.queued.id) {
+            // the workers only end after the last job was sent
+            let _ = jobs.sender.send(job);
+        }
+        true
+    }
+
+    /// The item a queued entry was found under. Entries added by hand and those
+    /// of removed items are downloaded with the options of the module.
+    fn queued_item(&self, items: &[Item], queued: &QueuedEntry) -> Item {
+        items
+            .iter()
+            .find(|item| {
+                queued.category.as_deref() != Some(queue::CATEGORY)
+                    && item.name == queued.item
+                    && item.category == queued.category
+            })
+            .cloned()
+            .unwrap_or_else(|| Item {
+                name: queued.item.clone(),
+                category: queued.category.clone(),
+                urls: vec![queued.item_url.clone()],
+                dir: queued.dir.clone(),
+                options: self.config().options.clone(),
+            })
+    }
+
+    fn dry_run(&self) -> bool {
+        self.config().dry_run.unwrap_or(false)
+    }
+
+    /// Set the state of a queued entry, nothing happens in a dry run
+    fn finish(&self, queued: &QueuedEntry, state: QueueState, error: Option<&DownloadError>) {
+        if queued.id != 0 && !self.dry_run() {
+            self.db
+                .finish_queued(queued.id, state, error.map(|e| e.message().to_string()));
+        }
+    }
+
+    /// Take jobs handed out by [`YtDlpModule::dispatch`] and download them until the queue is closed.
+    ///
+    /// Jobs are dropped once the module stops or `aborted` is set, they stay queued for the next cycle.
+    fn worker(&self, jobs: &Mutex<Receiver<Job>>, failures: &AtomicUsize, aborted: &AtomicBool) {
+        loop {
+            let job = jobs.lock().unwrap_or_else(PoisonError::into_inner).recv();
+            let Ok(job) = job else {
+                return;
+            };
+            if self.stopping() || aborted.load(Ordering::SeqCst) {
+                continue;
+            }
+
+            match self.download_entry(job) {
+                Ok(true) => {}
+                Ok(false) => {
+                    failures.fetch_add(1, Ordering::SeqCst);
+                }
+                Err(e) => {
+                    log::warn!("Stopping {} cycle early: {e}", self.name());
+                    failures.fetch_add(1, Ordering::SeqCst);
+                    aborted.store(true, Ordering::SeqCst);
+                }
+            }
+        }
+    }
+
+    /// Download a queued entry and record the result.
+    ///
+    /// # Returns
+    ///
+    /// Whether the download succeeded or was skipped, or an error if the module should stop
+    /// this cycle, because of rate limiting or a local problem like a full disk.
+    fn download_entry(&self, job: Job) -> Result<bool, DownloadError> {
+        let Job { item, queued } = job;
+        let entry = &queued.entry;
+
+        let Some(_claim) = self.db.claim(entry) else {
+            log::trace!(
+                "Skipping \"{}\" because it is downloading already",
+                entry.title
+            );
+            return Ok(true);
+        };
+        // the same entry may be listed under another item, which got to it first
+        if self.db.check_for_url(entry) {
+            log::trace!(
+                "Skipping \"{}\" because it was already downloaded",
+                entry.title
+            );
+            self.finish(&queued, QueueState::Done, None);
+            return Ok(true);
+        }
+        let attempt = self.db.get_attempt(entry);
+        if let Some(attempt) = &attempt {
+            if attempt.failed {
+                log::trace!(
+                    "Skipping \"{}\" because it failed {} times",
+                    entry.title,
+                    attempt.attempts
+                );
+                self.finish(&queued, QueueState::Failed, Some(&attempt.last_error));
+                return Ok(true);
+            }
+            if attempt.next_attempt > Local::now() {
+                log::trace!(
+                    "Skipping \"{}\" until {}",
+                    entry.title,
+                    attempt.next_attempt
+                );
+                return Ok(true);
+            }
+        }
+
+        let cwd = self.root_dir.join(&item.dir);
+        if self.dry_run() {
+            println!(
+                "[{}] Would download \"{}\" ({}) to {}",
+                self.name(),
+                entry.title,
+                entry.url,
+                cwd.display()
+            );
+            return Ok(true);
+        }
+
+        let priority = self.config().priority.unwrap_or(0);
+        let Some(_slot) = slots::acquire(priority, || self.stopping()) else {
+            return Err(DownloadError::Interrupted("Stopping".to_string()));
+        };
+        if queued.id != 0 && !self.db.start_queued(queued.id) {
+            log::trace!("Skipping \"{}\" because it left the queue", entry.title);
+            return Ok(true);
+        }
+        let running = bandwidth::Running::start(&self.name());
+        let result = self.download(&entry.url, &cwd, &item.options, &running);
+        drop(running);
+        match result {
+            Ok(moved) => {
+                // mark as downloaded
+                self.db.insert_url(entry);
+                self.db
+                    .update_new_downloads(&self.name(), &item.name, &queued.item_url);
+                self.db
+                    .insert_download(&self.download_record(entry.clone(), &item, &cwd, moved));
+                if attempt.is_some() {
+                    self.db.clear_attempt(entry);
+                }
+                self.finish(&queued, QueueState::Done, None);
+                log::info!("Downloaded \"{}\"", entry.title);
+                self.webhook_notify(entry, &item.name, None);
+                Ok(true)
+            }
+            Err(e) if e.is_local() => {
+                log::error!("Error downloading \"{}\"; Reason: {e}", entry.title);
+                self.finish(&queued, QueueState::Pending, Some(&e));
+                Err(e)
             }
             Err(e) => {
-                log::error!("Could not get videos from \"{item}\". Reason: {e}");
+                let stop = e.should_stop();
+                let attempt = Self::next_attempt(&item.options, attempt.as_ref(), e);
+                if attempt.failed {
+                    log::error!(
+                        "Giving up on \"{}\" after {} attempts; Reason: {}",
+                        entry.title,
+                        attempt.attempts,
+                        attempt.last_error
+                    );
+                } else {
+                    log::error!(
+                        "Error downloading \"{}\"; Retrying after {}; Reason: {}",
+                        entry.title,
+                        attempt.next_attempt,
+                        attempt.last_error
+                    );
+                }
+                let state = if attempt.failed {
+                    QueueState::Failed
+                } else {
+                    QueueState::Pending
+                };
+                self.finish(&queued, state, Some(&attempt.last_error));
+                self.db
+                    .record_attempt(entry, &self.name(), &item.name, &attempt);
+                self.webhook_notify(entry, &item.name, Some(&attempt));
+                if stop {
+                    return Err(attempt.last_error);
+                }
+                Ok(false)
             }
         }
     }
+
+    /// Download the queued entries and check the items selected by `check` for new ones.
+    ///
+    /// # Returns
+    ///
+    /// `false` if any fetch or download failed.
+    fn run_jobs(&self, check: Check) -> bool {
+        if let Check::All | Check::Regular | Check::Item(_) = check {
+            log::info!("Running {} Module", self.name());
+            // this cycle checks them anyway
+            self.recheck
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clear();
+        }
+        *self
+            .postponed
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = None;
+        let (items, concurrency, schedule) = {
+            let config = self.config();
+            (
+                config.items(),
+                config.concurrency.unwrap_or(1).max(1),
+                config.options.schedule.clone(),
+            )
+        };
+        let failures = AtomicUsize::new(0);
+        let items: Vec<Item> = items
+            .into_iter()
+            .filter_map(|item| {
+                item.map_err(|e| {
+                    log::error!("Invalid item in {}: {e}", self.name());
+                    failures.fetch_add(1, Ordering::SeqCst);
+                })
+                .ok()
+            })
+            .collect();
+
+        // set by a worker when the cycle should end early
+        let aborted = AtomicBool::new(false);
+        let (sender, receiver) = mpsc::channel();
+        let receiver = Mutex::new(receiver);
+        std::thread::scope(|scope| {
+            for _ in 0..concurrency {
+                scope.spawn(|| self.worker(&receiver, &failures, &aborted));
+            }
+
+            let mut jobs = Jobs {
+                sender,
+                sent: HashSet::new(),
+            };
+
+            // entries left from earlier cycles go first, in the order they were found
+            self.seen_added.store(queue::added(), Ordering::SeqCst);
+            let mut queued = self.db.queued(&self.name());
+            if let Check::Item(name) = &check {
+                queued.retain(|queued| {
+                    items.iter().any(|item| {
+                        item.matches(name)
+                            && item.name == queued.item
+                            && item.category == queued.category
+                    })
+                });
+            }
+            if !queued.is_empty() {
+                log::info!("Resuming {} queued downloads", queued.len());
+            }
+            for queued in queued {
+                let item = self.queued_item(&items, &queued);
+                self.dispatch(&mut jobs, Job { item, queued });
+            }
+
+            let checked: Vec<&Item> = match &check {
+                Check::All => items.iter().collect(),
+                Check::Regular => items
+                    .iter()
+                    .filter(|item| item.options.schedule == schedule)
+                    .collect(),
+                Check::Only(dirs) => items
+                    .iter()
+                    .filter(|item| dirs.contains(&item.dir))
+                    .collect(),
+                Check::Item(name) => items.iter().filter(|item| item.matches(name)).collect(),
+                Check::Queued => Vec::new(),
+            };
+            if !checked.is_empty() {
+                log::info!("Checking {} items", checked.len());
+                // items are listed while earlier ones download
+                for item in checked {
+                    if self.stopping() || aborted.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    match self.check_item(item, &mut jobs) {
+                        Ok(count) => {
+                            failures.fetch_add(count, Ordering::SeqCst);
+                        }
+                        Err(e) => {
+                            log::warn!("Stopping {} cycle early: {e}", self.name());
+                            failures.fetch_add(1, Ordering::SeqCst);
+                            aborted.store(true, Ordering::SeqCst);
+                        }
+                    }
+                }
+            }
+            drop(jobs);
+        });
+
+        let failures = failures.into_inner();
+        if failures > 0 {
+            log::warn!("{} finished with {failures} failures", self.name());
+        }
+        failures == 0
+    }
+
+    /// Sleep until `time`. Items added by a reload and entries added to the queue
+    /// in the meantime are handled right away.
+    fn wait_until(&self, time: DateTime<Local>) {
+        loop {
+            let recheck =
+                std::mem::take(&mut *self.recheck.lock().unwrap_or_else(PoisonError::into_inner));
+            if !recheck.is_empty() && !self.stopping() {
+                log::info!("Checking new and changed items of {}", self.name());
+                self.run_jobs(Check::Only(recheck));
+            }
+
+            let remaining = schedule::until(time);
+            if remaining.is_zero() || self.stopping() {
+                return;
+            }
+            shutdown::sleep_unless(remaining.min(queue::POLL_INTERVAL), || {
+                self.stopping()
+                    || queue::added() != self.seen_added.load(Ordering::SeqCst)
+                    || !self
+                        .recheck
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .is_empty()
+            });
+
+            // also counts URLs added for other modules, so it is taken before looking
+            let added = queue::added();
+            let pending = self.db.queued(&self.name());
+            let new = {
+                let mut dispatched = self
+                    .dispatched
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner);
+                // no pass runs now, so only postponed and retried entries are still pending
+                dispatched.retain(|id| pending.iter().any(|queued| queued.id == *id));
+                pending
+                    .iter()
+                    .any(|queued| !dispatched.contains(&queued.id))
+            };
+            self.seen_added.store(added, Ordering::SeqCst);
+            if new && !self.stopping() {
+                log::info!("Downloading entries added to the queue of {}", self.name());
+                self.run_jobs(Check::Queued);
+            }
+        }
+    }
+
+    /// Compute the state after another failed download.
+    ///
+    /// The delay before the next attempt starts at `retry_delay` and doubles with every failure.
+    /// Permanent errors give up right away.
+    fn next_attempt(
+        options: &DownloadOptions,
+        previous: Option<&Attempt>,
+        error: DownloadError,
+    ) -> Attempt {
+        let options = options.resolved();
+        let attempts = previous.map_or(0, |a| a.attempts) + 1;
+        let retry_delay = options.retry_delay.unwrap_or_default();
+        // never wait longer than a month
+        let delay = retry_delay
+            .saturating_mul(1 << (attempts - 1).min(16))
+            .min(30 * 24 * 60);
+
+        Attempt {
+            attempts,
+            failed: error.is_permanent() || attempts >= options.max_attempts.unwrap_or_default(),
+            last_error: error,
+            next_attempt: chrono::Local::now() + chrono::Duration::minutes(delay as i64),
+        }
+    }
+
+    /// Build the database record for a completed download
+    fn download_record(
+        &self,
+        mut entry: Entry,
+        item: &Item,
+        cwd: &Path,
+        moved: Vec<MovedFile>,
+    ) -> Download {
+        let mut files = Vec::new();
+        for file in moved {
+            // flat listings often lack these, the full extraction during download has them
+            entry.upload_date = entry.upload_date.or(file.upload_date);
+            entry.duration = entry.duration.or(file.duration);
+            if let Some(path) = file.filepath {
+                files.push(cwd.join(path));
+            }
+        }
+
+        let size = files
+            .iter()
+            .filter_map(|path| std::fs::metadata(path).ok())
+            .map(|meta| meta.len())
+            .sum();
+
+        Download {
+            module: self.name(),
+            item: item.name.clone(),
+            category: item.category.clone(),
+            entry,
+            files,
+            size,
+        }
+    }
 }
 
 impl Module for YtDlpModule {
     fn name(&self) -> String {
-        self.config
+        self.config()
             .name
             .clone()
             .unwrap_or_else(|| "yt-dlp".to_string())
     }
 
+    fn run_cycle(&self) -> bool {
+        self.run_jobs(Check::All)
+    }
+
+    fn run_item(&self, name: &str) -> Option<bool> {
+        let found = self
+            .config()
+            .items()
+            .iter()
+            .flatten()
+            .any(|item| item.matches(name));
+        found.then(|| self.run_jobs(Check::Item(name.to_string())))
+    }
+
+    fn reconfigure(&self, config: &ModuleConfig) -> bool {
+        self.apply_config(config.yt_dlp_config())
+    }
+
     fn run(&self) {
-        loop {
-            log::info!("Running {} Module", self.name());
-            log::info!("Checking {} items", self.config.items.len());
-            for (item, item_url) in &self.config.items {
-                match item_url {
-                    toml::Value::String(item_url) => {
-                        self.check_item(item, item_url, &self.root_dir.join(item));
-                    }
-                    toml::Value::Array(_) => todo!(),
-                    toml::Value::Table(cat) => {
-                        let category = item;
-                        for (item, item_url) in cat {
-                            let item_url = item_url.as_str().unwrap();
-                            self.check_item(
-                                item,
-                                item_url,
-                                &self.root_dir.join(category).join(item),
-                            );
-                        }
-                    }
-                    _ => {}
+        let schedule = self.config().options.schedule.clone();
+        let schedule = schedule.as_deref().and_then(|expression| {
+            schedule::parse_schedule(expression)
+                .map_err(|e| log::error!("Ignoring schedule of {}: {e}", self.name()))
+                .ok()
+        });
+
+        // a schedule decides the first check as well, an interval starts right away
+        let mut next = Local::now();
+        if schedule.is_some() {
+            next = self.next_cycle(schedule.as_ref());
+            log::info!("{} scheduled for {}", self.name(), next.format("%F %R"));
+        }
+        // when items with a schedule of their own are checked next, with their expression
+        let mut item_times: HashMap<PathBuf, (String, DateTime<Local>)> = HashMap::new();
+
+        while !self.stopping() {
+            let now = Local::now();
+            let item_schedules = self.item_schedules();
+            item_times.retain(|dir, (expression, _)| {
+                item_schedules.iter().any(|(other, other_expression, _)| {
+                    other == dir && other_expression == expression
+                })
+            });
+            for (dir, expression, schedule) in item_schedules {
+                if let Some(time) = schedule.after(&now).next() {
+                    item_times.entry(dir).or_insert((expression, time));
                 }
             }
-            log::info!(
-                "{} complete. Sleeping for {} minutes...",
-                self.name(),
-                self.config.interval
-            );
-            std::thread::sleep(std::time::Duration::from_secs(self.config.interval * 60));
+
+            if next <= now {
+                self.run_jobs(Check::Regular);
+                next = self.next_cycle(schedule.as_ref());
+                log::info!(
+                    "{} complete. Next check at {}",
+                    self.name(),
+                    next.format("%F %R")
+                );
+            }
+
+            let now = Local::now();
+            let due: HashSet<PathBuf> = item_times
+                .iter()
+                .filter(|(_, (_, time))| *time <= now)
+                .map(|(dir, _)| dir.clone())
+                .collect();
+            if !due.is_empty() {
+                log::info!("Checking {} scheduled items of {}", due.len(), self.name());
+                item_times.retain(|dir, _| !due.contains(dir));
+                self.run_jobs(Check::Only(due));
+                // downloads postponed by this pass may start before the next cycle
+                if let Some(postponed) = *self
+                    .postponed
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                {
+                    next = next.min(postponed);
+                }
+                continue;
+            }
+
+            let wake = item_times
+                .values()
+                .map(|(_, time)| *time)
+                .fold(next, DateTime::min);
+            self.wait_until(wake);
         }
     }
+
+    fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        shutdown::wake();
+    }
+}
+
+/// Resolve a single URL into an [`Entry`] using `yt-dlp`
+pub fn resolve_url(url: &str) -> Result<Entry, DownloadError> {
+    YtDlpModule::get_latest_entries(url, Some(1))?
+        .into_iter()
+        .next()
+        .ok_or_else(|| DownloadError::Unavailable(format!("Nothing found at {url}")))
+}
+
+/// Resolve a URL into all of its [`Entry`]s using `yt-dlp`, a playlist has many
+pub fn resolve_all(url: &str) -> Result<Vec<Entry>, DownloadError> {
+    let entries = YtDlpModule::get_latest_entries(url, None)?;
+    if entries.is_empty() {
+        return Err(DownloadError::Unavailable(format!(
+            "Nothing found at {url}"
+        )));
+    }
+    Ok(entries)
 }
 
 impl YtDlpModule {
-    pub fn webhook_notify(&self, video_url: &str, video_title: &str, item: &str, success: bool) {
-        let request = serde_json::json!({
+    /// Notify all webhooks about a download.
+    ///
+    /// `failure` is the state after a failed download, `None` if the download succeeded.
+    pub fn webhook_notify(&self, entry: &Entry, item: &str, failure: Option<&Attempt>) {
+        let mut request = serde_json::json!({
             "module": self.name(),
-            "url": video_url,
-            "title": video_title,
+            "url": entry.url,
+            "title": entry.title,
+            "id": entry.id,
+            "extractor": entry.extractor,
             "item": item,
-            "success": success
+            "success": failure.is_none()
         });
+        if let Some(attempt) = failure {
+            request["error"] = attempt.last_error.message().into();
+            request["error_kind"] = attempt.last_error.kind().into();
+            request["attempts"] = attempt.attempts.into();
+            request["gave_up"] = attempt.failed.into();
+        }
 
         let client = reqwest::blocking::Client::new();
-        if let Some(webhooks) = &self.config.webhooks {
+        let webhooks = self.config().webhooks.clone();
+        if let Some(webhooks) = &webhooks {
             for url in webhooks {
-                client
+                if let Err(e) = client
                     .post(url)
                     .json(&request)
                     .send()
-                    .expect("Failed to send webhook request");
+                    .and_then(reqwest::blocking::Response::error_for_status)
+                {
+                    log::error!("Failed to send webhook request: {e}");
+                }
             }
         }
     }
 
-    /// A function to get the latest entries (title and URL) for a given channel with a specified limit.
+    /// A function to get the latest entries for a given channel with a specified limit.
     ///
     /// # Arguments
     ///
     /// * `channel` - The name of the `YouTube` channel.
-    /// * `limit` - The maximum number of entries to return.
+    /// * `limit` - The maximum number of entries to return, all if `None`.
     ///
     /// # Returns
     ///
-    /// A `Result` containing a vector of tuples if successful, where each tuple contains the title and URL of an entry.
-    /// An error message if execution of `yt-dlp` fails.
-    fn get_latest_entries(channel: &str, limit: u64) -> Result<Vec<(String, String)>, String> {
-        let output = Command::new("yt-dlp")
+    /// A `Result` containing a vector of [`Entry`] parsed from the JSON listing of `yt-dlp`.
+    /// A classified error if execution of `yt-dlp` fails.
+    fn get_latest_entries(channel: &str, limit: Option<u64>) -> Result<Vec<Entry>, DownloadError> {
+        let mut command = Command::new("yt-dlp");
+        let mut command = command
             .arg("--no-warnings")
             .arg("--flat-playlist")
             .arg("--skip-download")
-            .arg("--print")
-            .arg("title,webpage_url")
-            .arg("--playlist-end")
-            .arg(limit.to_string())
-            .arg(channel)
-            .output()
-            .expect("Failed to execute yt-dlp");
+            .arg("--dump-json");
+        if let Some(limit) = limit {
+            command = command.arg("--playlist-end").arg(limit.to_string());
+        }
+        let command = command.arg(channel);
+        // listings are cheap to redo, so they are stopped right away
+        let output = shutdown::output(command, std::time::Duration::ZERO)
+            .map_err(|e| DownloadError::from_io("yt-dlp", &e))?;
 
         if !output.status.success() {
-            return Err(String::from_utf8(output.stderr).unwrap());
+            if shutdown::requested() {
+                return Err(DownloadError::Interrupted(format!(
+                    "Listing {channel} was stopped"
+                )));
+            }
+            return Err(DownloadError::from_stderr(&String::from_utf8_lossy(
+                &output.stderr,
+            )));
         }
 
-        let reader = BufReader::new(&output.stdout[..]);
-        let mut videos = Vec::new();
-        let mut lines = reader.lines();
-        while let (Some(title), Some(url)) = (lines.next(), lines.next()) {
-            if let (Ok(title), Ok(url)) = (title, url) {
-                videos.push((title, url));
+        let videos = Entry::parse_listing(&String::from_utf8_lossy(&output.stdout));
+
+        Ok(videos
+            .into_iter()
+            .take(limit.map_or(usize::MAX, |limit| limit as usize))
+            .collect())
+    }
+
+    /// Download a single entry into `cwd` with its share of the bandwidth.
+    ///
+    /// When the share changes, the download is restarted with the new one
+    /// and `yt-dlp` continues the partial download.
+    ///
+    /// # Returns
+    ///
+    /// The final location and metadata of every file `yt-dlp` moved into place.
+    fn download(
+        &self,
+        video_url: &str,
+        cwd: &PathBuf,
+        options: &DownloadOptions,
+        running: &bandwidth::Running,
+    ) -> Result<Vec<MovedFile>, DownloadError> {
+        loop {
+            let rate_limit = running.share(self.config().rate_limit.as_ref());
+            let changed = || running.share(self.config().rate_limit.as_ref()) != rate_limit;
+            if let Some(moved) = self.download_at(video_url, cwd, options, rate_limit, changed)? {
+                return Ok(moved);
             }
+            log::debug!("Restarting download of {video_url} with its new bandwidth share");
         }
-
-        Ok(videos.into_iter().take(limit as usize).collect())
     }
 
-    fn download(&self, video_url: &str, cwd: &PathBuf) -> Result<(), String> {
-        ensure_dir_exists(cwd);
+    /// Download a single entry into `cwd`, limited to `rate_limit` bytes per second.
+    ///
+    /// # Returns
+    ///
+    /// The moved files, `None` if `changed` returned true and the download was stopped.
+    fn download_at(
+        &self,
+        video_url: &str,
+        cwd: &PathBuf,
+        options: &DownloadOptions,
+        rate_limit: Option<u64>,
+        changed: impl Fn() -> bool,
+    ) -> Result<Option<Vec<MovedFile>>, DownloadError> {
+        std::fs::create_dir_all(cwd).map_err(|e| {
+            let message = format!("Could not create {}: {e}", cwd.display());
+            if e.kind() == std::io::ErrorKind::StorageFull {
+                DownloadError::DiskFull(message)
+            } else {
+                DownloadError::Other(message)
+            }
+        })?;
+        let options = options.resolved();
         let mut command = Command::new("yt-dlp");
         let mut command = command.current_dir(cwd).arg("--downloader").arg("aria2c");
 
-        if self.config.write_thumbnail.unwrap_or(true) {
+        if options.write_thumbnail == Some(true) {
             command = command.arg("--write-thumbnail");
         }
-        if self.config.write_description.unwrap_or(false) {
+        if options.write_description == Some(true) {
             command = command.arg("--write-description");
         }
-        if self.config.write_info_json.unwrap_or(false) {
+        if options.write_info_json == Some(true) {
             command = command.arg("--write-info-json");
         }
-        if self.config.write_comments.unwrap_or(false) {
+        if options.write_comments == Some(true) {
             command = command.arg("--write-comments");
         }
-        if self.config.write_subs.unwrap_or(false) {
+        if options.write_subs == Some(true) {
             command = command.arg("--write-subs");
         }
-        if self.config.audio_only.unwrap_or(false) {
+        if options.audio_only == Some(true) {
             command = command.arg("--extract-audio");
         }
-        if let Some(audio_format) = &self.config.audio_format {
+        if let Some(audio_format) = &options.audio_format {
             command = command.arg("--audio-format").arg(audio_format);
         }
 
-        if self.config.embed_chapters.unwrap_or(true) {
+        if options.embed_chapters == Some(true) {
             command = command.arg("--embed-chapters");
         }
-        if self.config.embed_info_json.unwrap_or(true) {
+        if options.embed_info_json == Some(true) {
             command = command.arg("--embed-info-json");
         }
-        if self.config.embed_metadata.unwrap_or(true) {
+        if options.embed_metadata == Some(true) {
             command = command.arg("--embed-metadata");
         }
-        if self.config.embed_subs.unwrap_or(false) {
+        if options.embed_subs == Some(true) {
             command = command.arg("--embed-subs");
         }
-        if self.config.embed_thumbnail.unwrap_or(true) {
+        if options.embed_thumbnail == Some(true) {
             command = command.arg("--embed-thumbnail");
         }
 
-        if self.config.split_chapters.unwrap_or(false) {
+        if options.split_chapters == Some(true) {
             command = command.arg("--split-chapters");
         }
 
-        if let Some(format) = &self.config.format {
+        if let Some(format) = &options.format {
             command = command.arg("--format").arg(format);
         }
-        if let Some(cookie) = &self.config.cookie {
+        if let Some(rate_limit) = rate_limit {
+            // --limit-rate only applies to the native downloader yt-dlp uses for some formats
+            command = command
+                .arg("--limit-rate")
+                .arg(rate_limit.to_string())
+                .arg("--downloader-args")
+                .arg(format!("aria2c:--max-overall-download-limit={rate_limit}"));
+        }
+        if let Some(cookie) = &options.cookie {
             command = command.arg("--cookies").arg(cookie);
         }
 
-        let output = command
-            .arg("--convert-thumbnails")
-            .arg(self.config.thumbnail_format.as_deref().unwrap_or("jpg"))
-            .arg("-o")
-            .arg(
-                self.config
-                    .output_format
-                    .as_deref()
-                    .unwrap_or("%(title)s.%(ext)s"),
-            )
-            .arg(video_url)
-            .output()
-            .map_err(|_| "yt-dlp command failed".to_string())?;
+        if let Some(thumbnail_format) = &options.thumbnail_format {
+            command = command.arg("--convert-thumbnails").arg(thumbnail_format);
+        }
+        if let Some(output_format) = &options.output_format {
+            command = command.arg("-o").arg(output_format);
+        }
+
+        let command = command
+            .arg("--no-simulate")
+            .arg("--print")
+            .arg("after_move:%(.{filepath,upload_date,duration})j")
+            .arg(video_url);
+        let Some(output) = shutdown::output_unless(command, shutdown::grace_period(), changed)
+            .map_err(|e| DownloadError::from_io("yt-dlp", &e))?
+        else {
+            return Ok(None);
+        };
 
         if !output.status.success() {
-            let error_message = String::from_utf8_lossy(&output.stderr).to_string();
-            return Err(error_message);
+            if shutdown::requested() {
+                return Err(DownloadError::Interrupted(format!(
+                    "Download of {video_url} was stopped"
+                )));
+            }
+            return Err(DownloadError::from_stderr(&String::from_utf8_lossy(
+                &output.stderr,
+            )));
         }
 
-        Ok(())
+        Ok(Some(
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .filter_map(|line| serde_json::from_str(line).ok())
+                .collect(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(max_attempts: u32, retry_delay: u64) -> DownloadOptions {
+        DownloadOptions {
+            max_attempts: Some(max_attempts),
+            retry_delay: Some(retry_delay),
+            ..DownloadOptions::default()
+        }
+    }
+
+    fn after(options: &DownloadOptions, attempts: u32, error: DownloadError) -> Attempt {
+        let previous = Attempt {
+            attempts,
+            last_error: DownloadError::Network("timed out".to_string()),
+            next_attempt: Local::now(),
+            failed: false,
+        };
+        YtDlpModule::next_attempt(options, (attempts > 0).then_some(&previous), error)
+    }
+
+    /// Minutes until the next attempt, rounded to ignore the time the test took
+    fn delay(attempt: &Attempt) -> i64 {
+        ((attempt.next_attempt - Local::now()).num_seconds() + 30) / 60
+    }
+
+    fn network() -> DownloadError {
+        DownloadError::Network("timed out".to_string())
+    }
+
+    #[test]
+    fn delay_doubles() {
+        let options = options(10, 30);
+        for (previous, minutes) in [(0, 30), (1, 60), (2, 120), (3, 240)] {
+            let attempt = after(&options, previous, network());
+            assert_eq!(attempt.attempts, previous + 1);
+            assert_eq!(delay(&attempt), minutes);
+            assert!(!attempt.failed);
+        }
+
+        // the fallbacks apply without options
+        let attempt = after(&DownloadOptions::default(), 0, network());
+        assert_eq!(delay(&attempt), 30);
+    }
+
+    #[test]
+    fn delay_is_capped_at_a_month() {
+        let options = options(100, 24 * 60);
+        assert_eq!(delay(&after(&options, 4, network())), 16 * 24 * 60);
+        assert_eq!(delay(&after(&options, 5, network())), 30 * 24 * 60);
+        // far beyond the point where the factor would overflow
+        assert_eq!(delay(&after(&options, 80, network())), 30 * 24 * 60);
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let options = options(3, 30);
+        assert!(!after(&options, 0, network()).failed);
+        assert!(!after(&options, 1, network()).failed);
+        assert!(after(&options, 2, network()).failed);
+
+        let once = self::options(1, 30);
+        assert!(after(&once, 0, network()).failed);
+    }
+
+    #[test]
+    fn permanent_errors_give_up_right_away() {
+        let options = options(10, 30);
+        let attempt = after(
+            &options,
+            0,
+            DownloadError::Unavailable("removed".to_string()),
+        );
+        assert!(attempt.failed);
+        assert_eq!(attempt.attempts, 1);
+
+        let attempt = after(
+            &options,
+            0,
+            DownloadError::RateLimited("slow down".to_string()),
+        );
+        assert!(!attempt.failed);
     }
 }